@@ -0,0 +1,116 @@
+## Forms
+
+login-title = Log In
+register-title = Register
+login-magic-title = Email Login Link
+login-magic-confirm-title = Log In with Your Link
+login-magic-sent = If an account exists for that email, a login link is on its way. It expires in 15 minutes.
+login-magic-invalid-email = Please enter a valid email.
+login-magic-invalid-link = This login link is invalid or has expired.
+login-magic-email-subject = Your login link
+login-magic-email-body =
+    Hello { $name },
+
+    Use this link to log in: { $link }
+
+    It expires in 15 minutes and can only be used once.
+
+field-email = Email
+    .placeholder = Please enter a valid email.
+field-password = Password
+    .placeholder = Please enter a valid password.
+field-remember = Remember me
+field-first_name = First Name
+    .placeholder = Please enter your first name.
+field-last_name = Last Name
+    .placeholder = Please enter your last name.
+field-confirm_password = Confirm Password
+    .placeholder = Please confirm your password.
+
+sign-in-with = Sign in with { $provider }
+email-login-link = Email me a login link instead
+back = Back
+home = Home
+request-id = Request ID: { $id }
+language = Language
+
+## Pages
+
+home-title = Home
+home-greeting = Hello, authenticated user!
+account-title = Account
+account-tokens = Personal access tokens
+account-tokens-help = Tokens let scripts call the JSON API with an <code>Authorization: Bearer</code> header.
+account-new-token = Copy your new token now, it won't be shown again:
+account-token-name = Token name
+account-expires-in-days = Expires in { $days } days
+account-expires-in-year = Expires in 1 year
+account-never-expires = Never expires
+account-create-token = Create token
+account-name = Name
+account-scopes = Scopes
+account-created = Created
+account-expires = Expires
+account-last-used = Last used
+account-never = never
+account-revoke = Revoke
+account-no-tokens = No tokens yet.
+account-error-name = Name must be 1 to 100 characters
+account-error-scopes = Select at least one scope
+account-error-create = An error occured creating the token
+auth-events-title = Auth Events
+auth-events-user-id = User id
+auth-events-any-event = Any event
+auth-events-any-outcome = Any outcome
+auth-events-filter = Filter
+auth-events-time = Time
+auth-events-event = Event
+auth-events-outcome = Outcome
+auth-events-user = User
+auth-events-ip = IP
+auth-events-user-agent = User Agent
+auth-events-none = No events found.
+auth-event-login = login
+auth-event-register = register
+auth-event-logout = logout
+auth-outcome-success = success
+auth-outcome-failure = failure
+
+## Single sign-on
+
+oidc-login-failed = Sign in failed: { $reason }
+oidc-error-expired = the login request has expired
+oidc-error-no-code = no authorization code
+oidc-error-access-denied = access was denied
+oidc-error-sign-in-there = the identity provider needs you to sign in there first
+oidc-error-unavailable = the identity provider is unavailable
+oidc-error-provider = the identity provider reported an error
+oidc-error-invalid-token = the identity provider's answer could not be verified
+oidc-error-missing-email = the identity provider did not share an email address
+oidc-error-unverified-email = the identity provider has not verified this email address
+oidc-error-internal = an internal error occured
+
+## Validation errors, by code
+
+error-blank = Required
+error-invalid = Invalid Credentials
+error-email_taken = Email is already registered
+error-password_length = Password must be at least { $min } characters
+error-password_uppercase = Password must contain at least one uppercase character
+error-password_lowercase = Password must contain at least one lowercase character
+error-password_number = Password must contain at least one number
+error-password_special = Password must contain at least one special character
+error-password_spaces = Password must not contain spaces
+error-password_mismatch = Passwords must match
+error-unavailable = The service is temporarily unavailable, please try again
+error-internal_error = An internal error occured
+
+## Error responses
+
+api-error-unauthorized = Authentication required
+api-error-not_found = Resource not found
+api-error-page_not_found = Page Not Found
+api-error-method_not_allowed = Method not allowed
+api-error-validation_failed = The request failed validation
+api-error-missing_scope = Token is missing the '{ $scope }' scope
+api-error-admin_required = Admin role required
@@ -0,0 +1,116 @@
+## Forms
+
+login-title = Connexion
+register-title = Inscription
+login-magic-title = Lien de connexion par e-mail
+login-magic-confirm-title = Se connecter avec votre lien
+login-magic-sent = Si un compte existe pour cette adresse, un lien de connexion est en route. Il expire dans 15 minutes.
+login-magic-invalid-email = Veuillez saisir une adresse e-mail valide.
+login-magic-invalid-link = Ce lien de connexion est invalide ou a expiré.
+login-magic-email-subject = Votre lien de connexion
+login-magic-email-body =
+    Bonjour { $name },
+
+    Utilisez ce lien pour vous connecter : { $link }
+
+    Il expire dans 15 minutes et ne peut être utilisé qu'une seule fois.
+
+field-email = E-mail
+    .placeholder = Veuillez saisir une adresse e-mail valide.
+field-password = Mot de passe
+    .placeholder = Veuillez saisir un mot de passe valide.
+field-remember = Se souvenir de moi
+field-first_name = Prénom
+    .placeholder = Veuillez saisir votre prénom.
+field-last_name = Nom
+    .placeholder = Veuillez saisir votre nom.
+field-confirm_password = Confirmer le mot de passe
+    .placeholder = Veuillez confirmer votre mot de passe.
+
+sign-in-with = Se connecter avec { $provider }
+email-login-link = Recevoir plutôt un lien de connexion par e-mail
+back = Retour
+home = Accueil
+request-id = Identifiant de requête : { $id }
+language = Langue
+
+## Pages
+
+home-title = Accueil
+home-greeting = Bonjour, utilisateur authentifié !
+account-title = Compte
+account-tokens = Jetons d'accès personnels
+account-tokens-help = Les jetons permettent aux scripts d'appeler l'API JSON avec un en-tête <code>Authorization: Bearer</code>.
+account-new-token = Copiez votre nouveau jeton maintenant, il ne sera plus affiché :
+account-token-name = Nom du jeton
+account-expires-in-days = Expire dans { $days } jours
+account-expires-in-year = Expire dans 1 an
+account-never-expires = N'expire jamais
+account-create-token = Créer un jeton
+account-name = Nom
+account-scopes = Portées
+account-created = Créé le
+account-expires = Expire le
+account-last-used = Dernière utilisation
+account-never = jamais
+account-revoke = Révoquer
+account-no-tokens = Aucun jeton pour l'instant.
+account-error-name = Le nom doit contenir de 1 à 100 caractères
+account-error-scopes = Sélectionnez au moins une portée
+account-error-create = Une erreur s'est produite lors de la création du jeton
+auth-events-title = Événements d'authentification
+auth-events-user-id = Identifiant utilisateur
+auth-events-any-event = Tout événement
+auth-events-any-outcome = Tout résultat
+auth-events-filter = Filtrer
+auth-events-time = Date
+auth-events-event = Événement
+auth-events-outcome = Résultat
+auth-events-user = Utilisateur
+auth-events-ip = IP
+auth-events-user-agent = Agent utilisateur
+auth-events-none = Aucun événement trouvé.
+auth-event-login = connexion
+auth-event-register = inscription
+auth-event-logout = déconnexion
+auth-outcome-success = réussite
+auth-outcome-failure = échec
+
+## Single sign-on
+
+oidc-login-failed = Échec de la connexion : { $reason }
+oidc-error-expired = la demande de connexion a expiré
+oidc-error-no-code = aucun code d'autorisation
+oidc-error-access-denied = l'accès a été refusé
+oidc-error-sign-in-there = le fournisseur d'identité vous demande de vous y connecter d'abord
+oidc-error-unavailable = le fournisseur d'identité est indisponible
+oidc-error-provider = le fournisseur d'identité a signalé une erreur
+oidc-error-invalid-token = la réponse du fournisseur d'identité n'a pas pu être vérifiée
+oidc-error-missing-email = le fournisseur d'identité n'a pas partagé d'adresse e-mail
+oidc-error-unverified-email = le fournisseur d'identité n'a pas vérifié cette adresse e-mail
+oidc-error-internal = une erreur interne s'est produite
+
+## Validation errors, by code
+
+error-blank = Obligatoire
+error-invalid = Identifiants invalides
+error-email_taken = Cette adresse e-mail est déjà enregistrée
+error-password_length = Le mot de passe doit contenir au moins { $min } caractères
+error-password_uppercase = Le mot de passe doit contenir au moins une majuscule
+error-password_lowercase = Le mot de passe doit contenir au moins une minuscule
+error-password_number = Le mot de passe doit contenir au moins un chiffre
+error-password_special = Le mot de passe doit contenir au moins un caractère spécial
+error-password_spaces = Le mot de passe ne doit pas contenir d'espaces
+error-password_mismatch = Les mots de passe doivent correspondre
+error-unavailable = Le service est temporairement indisponible, veuillez réessayer
+error-internal_error = Une erreur interne s'est produite
+
+## Error responses
+
+api-error-unauthorized = Authentification requise
+api-error-not_found = Ressource introuvable
+api-error-page_not_found = Page introuvable
+api-error-method_not_allowed = Méthode non autorisée
+api-error-validation_failed = La requête n'a pas passé la validation
+api-error-missing_scope = Il manque la portée « { $scope } » au jeton
+api-error-admin_required = Rôle administrateur requis
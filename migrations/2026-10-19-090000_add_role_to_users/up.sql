@@ -0,0 +1 @@
+alter table users add column role varchar(32) not null default 'user';
@@ -0,0 +1,13 @@
+create table auth_events (
+	id serial primary key,
+	user_id integer references users(id) on delete set null,
+	email varchar(255),
+	ip varchar(64),
+	user_agent text,
+	event varchar(32) not null,
+	outcome varchar(32) not null,
+	created_at timestamptz not null default now()
+);
+
+create index auth_events_user_id_idx on auth_events (user_id);
+create index auth_events_created_at_idx on auth_events (created_at);
@@ -0,0 +1,10 @@
+create table remember_tokens (
+	id serial primary key,
+	user_id integer not null references users(id) on delete cascade,
+	selector varchar(32) unique not null,
+	validator_hash varchar(64) not null,
+	expires_at timestamptz not null,
+	created_at timestamptz not null default now()
+);
+
+create index remember_tokens_user_id_idx on remember_tokens (user_id);
@@ -0,0 +1,8 @@
+create table login_links (
+	id serial primary key,
+	user_id integer not null references users(id) on delete cascade,
+	token_hash varchar(64) unique not null,
+	expires_at timestamptz not null,
+	used_at timestamptz,
+	created_at timestamptz not null default now()
+);
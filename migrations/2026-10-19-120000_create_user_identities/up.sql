@@ -0,0 +1,11 @@
+create table user_identities (
+	id serial primary key,
+	user_id integer not null references users(id) on delete cascade,
+	provider varchar(255) not null,
+	subject varchar(255) not null,
+	email varchar(255),
+	created_at timestamptz not null default now(),
+	unique (provider, subject)
+);
+
+create index user_identities_user_id_idx on user_identities (user_id);
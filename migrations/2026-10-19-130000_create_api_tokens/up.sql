@@ -0,0 +1,12 @@
+create table api_tokens (
+	id serial primary key,
+	user_id integer not null references users(id) on delete cascade,
+	name varchar(100) not null,
+	token_hash varchar(64) unique not null,
+	scopes varchar(255) not null,
+	expires_at timestamptz,
+	last_used_at timestamptz,
+	created_at timestamptz not null default now()
+);
+
+create index api_tokens_user_id_idx on api_tokens (user_id);
@@ -0,0 +1,2 @@
+-- Intentionally left as a no-op: the seeded account's password is public, so
+-- rolling back must not put it back. Use `web_app-admin seed` for dev users.
@@ -0,0 +1,5 @@
+-- create_users seeds an account whose password is public. Remove it again,
+-- unless its password has been changed since.
+delete from users
+where email = 'frodo@theshire.com'
+	and password = '$argon2id$v=19$m=4096,t=3,p=1$A2uYmfHJZkAQ55CCvpTujA$aBoQLUaRrqIQl33JcKRqy+x7a/WQBpNEsuJJjCUylyk';
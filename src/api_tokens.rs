@@ -0,0 +1,116 @@
+use crate::models::{ApiToken, NewApiToken, User};
+use crate::tokens::{hash_token, random_token};
+use crate::{connect, DbPool};
+use chrono::Utc;
+use diesel::{delete, insert_into, prelude::*, update};
+
+///Prefix of every personal access token, so leaked tokens are easy to spot.
+pub const TOKEN_PREFIX: &str = "wa_";
+pub const READ: &str = "read";
+pub const WRITE: &str = "write";
+
+///Creates a token and returns it along with its plaintext value. The plaintext
+///is never stored, so it can only be shown to the user once.
+pub fn create(
+    pool: &DbPool,
+    user_id: i32,
+    name: &str,
+    scopes: &[&str],
+    expires_in_days: Option<i64>,
+) -> QueryResult<(ApiToken, String)> {
+    use crate::schema::api_tokens::dsl::api_tokens;
+    let token = format!("{TOKEN_PREFIX}{}", random_token(32));
+    let new_token = NewApiToken {
+        user_id,
+        name: name.to_string(),
+        token_hash: hash_token(&token),
+        scopes: scopes.join(" "),
+        expires_at: expires_in_days
+            .map(|days| (Utc::now() + chrono::Duration::days(days)).naive_utc()),
+    };
+    let conn = &mut connect(pool)?;
+    let api_token = insert_into(api_tokens)
+        .values(new_token)
+        .get_result::<ApiToken>(conn)?;
+    Ok((api_token, token))
+}
+
+pub fn list(pool: &DbPool, account_id: i32) -> QueryResult<Vec<ApiToken>> {
+    use crate::schema::api_tokens::dsl::*;
+    let conn = &mut connect(pool)?;
+    api_tokens
+        .filter(user_id.eq(account_id))
+        .order(created_at.desc())
+        .load::<ApiToken>(conn)
+}
+
+///Deletes one of the user's tokens, returning whether it existed.
+pub fn revoke(pool: &DbPool, account_id: i32, token_id: i32) -> QueryResult<bool> {
+    use crate::schema::api_tokens::dsl::*;
+    let conn = &mut connect(pool)?;
+    let deleted = delete(
+        api_tokens
+            .filter(id.eq(token_id))
+            .filter(user_id.eq(account_id)),
+    )
+    .execute(conn)?;
+    Ok(deleted > 0)
+}
+
+///Resolves a bearer token to its user and scopes, recording when it was last
+///used. Unknown and expired tokens yield `None`.
+pub fn authenticate(pool: &DbPool, token: &str) -> QueryResult<Option<(User, Vec<String>)>> {
+    use crate::schema::api_tokens::dsl::*;
+    if !token.starts_with(TOKEN_PREFIX) {
+        return Ok(None);
+    };
+    let now = Utc::now().naive_utc();
+    let conn = &mut connect(pool)?;
+    //looked up by the hash of the whole token, so lookup timing can at most
+    //reveal a prefix of a hash, never of a token
+    let api_token = update(
+        api_tokens
+            .filter(token_hash.eq(hash_token(token)))
+            .filter(expires_at.is_null().or(expires_at.gt(now))),
+    )
+    .set(last_used_at.eq(now))
+    .get_result::<ApiToken>(conn)
+    .optional()?;
+    let api_token = match api_token {
+        Some(api_token) => api_token,
+        None => return Ok(None),
+    };
+    let user = User::find(pool, api_token.user_id)?;
+    let token_scopes = api_token.scopes.split(' ').map(String::from).collect();
+    Ok(Some((user, token_scopes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_token_lifecycle() {
+        let pool = testing::pool();
+        let user = testing::create_user("user");
+        let (api_token, token) = create(&pool, user.id, "cli", &[READ], Some(30)).unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_ne!(api_token.token_hash, token);
+        let (token_user, scopes) = authenticate(&pool, &token).unwrap().unwrap();
+        assert_eq!(token_user.id, user.id);
+        assert_eq!(scopes, vec![READ]);
+        assert!(revoke(&pool, user.id, api_token.id).unwrap());
+        assert!(authenticate(&pool, &token).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_expired_token_is_rejected() {
+        let pool = testing::pool();
+        let user = testing::create_user("user");
+        let (api_token, token) = create(&pool, user.id, "expired", &[READ], Some(-1)).unwrap();
+        assert!(authenticate(&pool, &token).unwrap().is_none());
+        assert!(authenticate(&pool, "not-a-token").unwrap().is_none());
+        revoke(&pool, user.id, api_token.id).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//! The application factory. `main` and the tests build the app the same way,
+//! so tests exercise the real middleware stack and route table.
+use crate::errors::{self, not_found};
+use crate::mailer::{self, Mailer};
+use crate::oidc::OidcConfig;
+use crate::proxy::TrustedProxies;
+use crate::repository::{DieselUserRepository, UserRepository};
+use crate::routes::{self, health, metrics as metrics_routes};
+use crate::services::{auth::AuthService, users::UserService};
+use crate::shutdown::Shutdown;
+use crate::{metrics, pool, remember, telemetry, tls, DbPool};
+use actix_files as fs;
+use actix_identity::IdentityMiddleware;
+use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+use actix_web::{
+    body::MessageBody,
+    cookie::Key,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+    web, App, Error, HttpRequest,
+};
+use actix_web_lab::middleware::from_fn;
+use std::env;
+use std::sync::Arc;
+
+///What the app is built from. Cloned into every worker.
+#[derive(Clone)]
+pub struct AppConfig {
+    pub pool: DbPool,
+    pub mailer: Arc<dyn Mailer>,
+    pub oidc: Option<OidcConfig>,
+    ///Signs the session cookies.
+    pub session_key: Key,
+    ///Flips `/readyz` to unready when the server starts shutting down.
+    pub shutdown: Shutdown,
+    ///Only send the session cookie over HTTPS. Set when the server terminates
+    ///TLS itself.
+    pub secure_cookies: bool,
+    ///Proxies whose forwarded headers are believed, see [`ClientInfo`].
+    ///
+    ///[`ClientInfo`]: crate::proxy::ClientInfo
+    pub trusted_proxies: TrustedProxies,
+}
+
+///Shortest SESSION_KEY accepted, in bytes.
+const MIN_SESSION_KEY_LENGTH: usize = 64;
+
+///Key of the session cookies from the SESSION_KEY secret. The session holds
+///the user id that authorizes every request, so anyone knowing the key can
+///sign in as anyone: there's no default.
+fn session_key(secret: Option<String>) -> Result<Key, String> {
+    let secret = secret.ok_or_else(|| {
+        format!("SESSION_KEY must be set to a secret of at least {MIN_SESSION_KEY_LENGTH} bytes")
+    })?;
+    if secret.len() < MIN_SESSION_KEY_LENGTH {
+        return Err(format!(
+            "SESSION_KEY must be at least {MIN_SESSION_KEY_LENGTH} bytes long, it is {}",
+            secret.len()
+        ));
+    };
+    Ok(Key::from(secret.as_bytes()))
+}
+
+impl AppConfig {
+    ///Global pool, and session key, mailer and OIDC provider from the
+    ///environment. Fails without a usable SESSION_KEY.
+    pub fn from_env() -> Result<AppConfig, String> {
+        Ok(AppConfig {
+            pool: pool().clone(),
+            mailer: mailer::from_env(),
+            oidc: OidcConfig::from_env(),
+            session_key: session_key(env::var("SESSION_KEY").ok())?,
+            shutdown: Shutdown::default(),
+            secure_cookies: false,
+            trusted_proxies: TrustedProxies::default(),
+        })
+    }
+}
+
+pub fn build_app(
+    config: AppConfig,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    let users: Arc<dyn UserRepository> = Arc::new(DieselUserRepository::new(config.pool.clone()));
+    App::new()
+        .app_data(web::Data::new(config.pool))
+        .app_data(web::Data::from(config.mailer))
+        .app_data(web::Data::new(AuthService::new(users.clone())))
+        .app_data(web::Data::new(UserService::new(users)))
+        .app_data(web::Data::new(config.shutdown))
+        .app_data(web::Data::new(config.trusted_proxies))
+        .configure(|cfg| {
+            if let Some(oidc_config) = config.oidc {
+                cfg.app_data(web::Data::new(oidc_config));
+            };
+        })
+        //probes and metrics skip the session, identity and logging middleware below
+        .configure(health::index)
+        .configure(metrics_routes::index)
+        .service(
+            web::scope("")
+                .wrap(from_fn(errors::negotiate_errors))
+                .wrap(from_fn(telemetry::record_user))
+                .wrap(from_fn(remember::restore_session))
+                .wrap(IdentityMiddleware::default())
+                .wrap(
+                    SessionMiddleware::builder(CookieSessionStore::default(), config.session_key)
+                        .cookie_secure(config.secure_cookies)
+                        .build(),
+                )
+                .wrap(from_fn(tls::secure_cookies))
+                .wrap(from_fn(metrics::track_requests))
+                .wrap(from_fn(telemetry::trace_requests))
+                .configure(routes::configure)
+                .service(fs::Files::new("/static", "./static"))
+                .default_service(web::to(not_found)),
+        )
+}
+
+///App of the plain HTTP listeners when the server terminates TLS: probes still
+///answer, everything else is redirected to HTTPS on `https_port`.
+pub fn build_redirect_app(
+    pool: DbPool,
+    shutdown: Shutdown,
+    https_port: u16,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .app_data(web::Data::new(pool))
+        .app_data(web::Data::new(shutdown))
+        .configure(health::index)
+        .default_service(web::to(move |req: HttpRequest| async move {
+            tls::redirect_to_https(&req, https_port)
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::take_route_table;
+    use crate::testing;
+    use actix_service::{IntoServiceFactory, ServiceFactory};
+    use actix_web::{http::header, test};
+    use std::collections::HashMap;
+
+    #[actix_web::test]
+    async fn test_routes_are_registered_once() {
+        take_route_table();
+        test::init_service(build_app(testing::config())).await;
+        let table = take_route_table();
+        let mut registrations = HashMap::new();
+        for (path, method) in &table {
+            *registrations
+                .entry((path.as_str(), method.as_str()))
+                .or_insert(0) += 1;
+        }
+        let duplicates: Vec<_> = registrations
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(route, _)| route)
+            .collect();
+        assert!(duplicates.is_empty(), "registered twice: {duplicates:?}");
+        for route in [
+            ("/login", "POST"),
+            ("/healthz", "GET"),
+            ("/metrics", "GET"),
+            ("/admin/auth-events", "GET"),
+            ("/api/v1/users/{id}", "PATCH"),
+            ("/login/oidc/callback", "GET"),
+        ] {
+            assert!(registrations.contains_key(&route), "{route:?} is missing");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_session_key_is_required() {
+        assert!(session_key(None).is_err());
+        assert!(session_key(Some("too short".repeat(7))).is_err());
+        let secret = "a".repeat(64);
+        assert_eq!(
+            session_key(Some(secret.clone())).unwrap().master(),
+            Key::from(secret.as_bytes()).master()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_redirect_app() {
+        let app = test::init_service(build_redirect_app(
+            testing::pool(),
+            Shutdown::default(),
+            8443,
+        ))
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/login?next=%2Fhome")
+            .insert_header((header::HOST, "example.com:8080"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 308);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "https://example.com:8443/login?next=%2Fhome"
+        );
+        let request = test::TestRequest::get().uri("/healthz").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_cookies_are_secure_over_tls() {
+        let user = testing::create_user("user");
+        let login = || {
+            test::TestRequest::post().uri("/login").set_form([
+                ("email", user.email.as_str()),
+                ("password", testing::TEST_PASSWORD),
+                ("remember", "on"),
+            ])
+        };
+        let app = test::init_service(build_app(testing::config())).await;
+        let response = test::call_service(&app, login().to_request()).await;
+        assert_eq!(response.status(), 303);
+        assert!(response
+            .response()
+            .cookies()
+            .all(|c| c.secure() != Some(true)));
+
+        let config = AppConfig {
+            secure_cookies: true,
+            ..testing::config()
+        };
+        //what the server hands apps on its TLS listeners
+        let tls = actix_web::dev::AppConfig::__priv_test_new(
+            true,
+            String::from("localhost"),
+            "127.0.0.1:443".parse().unwrap(),
+        );
+        let app = build_app(config)
+            .into_factory()
+            .new_service(tls)
+            .await
+            .unwrap();
+        let request = login().to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 303);
+        let cookies: Vec<_> = response.response().cookies().collect();
+        assert!(cookies.len() >= 2, "{cookies:?}");
+        assert!(cookies.iter().all(|c| c.secure() == Some(true)));
+    }
+}
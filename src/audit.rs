@@ -0,0 +1,248 @@
+use crate::models::{AuthEvent, NewAuthEvent};
+use crate::proxy::ClientInfo;
+use crate::{app_pool, connection_from, metrics, DbConnection, DbPool};
+use actix_web::{http::header, HttpRequest};
+use chrono::NaiveDate;
+use diesel::{insert_into, prelude::*};
+use serde::{Deserialize, Serialize};
+
+const MAX_RESULTS: i64 = 500;
+
+#[derive(Clone, Copy)]
+pub enum AuthEventKind {
+    Login,
+    Register,
+    Logout,
+}
+
+impl AuthEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthEventKind::Login => "login",
+            AuthEventKind::Register => "register",
+            AuthEventKind::Logout => "logout",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum AuthOutcome {
+    Success,
+    Failure,
+}
+
+impl AuthOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthOutcome::Success => "success",
+            AuthOutcome::Failure => "failure",
+        }
+    }
+}
+
+///Connection from the app's pool.
+fn connect(req: &HttpRequest) -> Result<DbConnection, String> {
+    match app_pool(req) {
+        Some(pool) => connection_from(&pool).map_err(|e| e.to_string()),
+        None => Err(String::from("the app has no database pool")),
+    }
+}
+
+///Records an authentication event for the request. Failing to write the audit
+///row, e.g. because the database is down, is logged but never interrupts the
+///login/register/logout flow itself.
+pub fn record(
+    req: &HttpRequest,
+    event: AuthEventKind,
+    outcome: AuthOutcome,
+    user_id: Option<i32>,
+    email: Option<&str>,
+) {
+    use crate::schema::auth_events::dsl::auth_events;
+    metrics::record_auth_event(event, outcome);
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    let new_event = NewAuthEvent {
+        user_id,
+        email: email.map(String::from),
+        ip: ClientInfo::of(req).ip.map(|ip| ip.to_string()),
+        user_agent,
+        event: event.as_str().to_string(),
+        outcome: outcome.as_str().to_string(),
+    };
+    let conn = &mut match connect(req) {
+        Ok(conn) => conn,
+        Err(e) => {
+            log::error!("Error recording {} auth event: {e}", event.as_str());
+            return;
+        }
+    };
+    if let Err(e) = insert_into(auth_events).values(new_event).execute(conn) {
+        log::error!("Error recording {} auth event: {e}", event.as_str());
+    };
+}
+
+///Query string filters for the admin audit log. Every value is optional and
+///empty strings (as submitted by the filter form) are ignored.
+#[derive(Deserialize, Serialize, Default)]
+pub struct AuthEventFilter {
+    pub email: Option<String>,
+    pub user_id: Option<String>,
+    pub event: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+fn parse_date(value: &Option<String>) -> Option<NaiveDate> {
+    non_empty(value).and_then(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d").ok())
+}
+
+///`LIKE` pattern matching values that contain `value` literally: its own `%`
+///and `_` aren't wildcards.
+fn contains_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+pub fn search(pool: &DbPool, filter: &AuthEventFilter) -> QueryResult<Vec<AuthEvent>> {
+    use crate::schema::auth_events::dsl::*;
+    let mut query = auth_events.into_boxed();
+    if let Some(value) = non_empty(&filter.email) {
+        query = query.filter(email.ilike(contains_pattern(value)));
+    };
+    if let Some(value) = non_empty(&filter.user_id).and_then(|v| v.parse::<i32>().ok()) {
+        query = query.filter(user_id.eq(value));
+    };
+    if let Some(value) = non_empty(&filter.event) {
+        query = query.filter(event.eq(value.to_string()));
+    };
+    if let Some(value) = non_empty(&filter.outcome) {
+        query = query.filter(outcome.eq(value.to_string()));
+    };
+    if let Some(value) = parse_date(&filter.since).and_then(|date| date.and_hms_opt(0, 0, 0)) {
+        query = query.filter(created_at.ge(value));
+    };
+    //nothing happens after the last day chrono knows, which has no next day
+    if let Some(value) = parse_date(&filter.until)
+        .and_then(|date| date.succ_opt())
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+    {
+        query = query.filter(created_at.lt(value));
+    };
+    let conn = &mut crate::connect(pool).map_err(|e| {
+        log::error!("Error searching auth events: {e}");
+        e
+    })?;
+    query
+        .order(created_at.desc())
+        .limit(MAX_RESULTS)
+        .load::<AuthEvent>(conn)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", value.replace('"', "\"\""));
+    };
+    value.to_string()
+}
+
+pub fn to_csv(events: &[AuthEvent]) -> String {
+    let mut csv = String::from("id,created_at,event,outcome,user_id,email,ip,user_agent\n");
+    for e in events {
+        let row = [
+            e.id.to_string(),
+            e.created_at.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            e.event.clone(),
+            e.outcome.clone(),
+            e.user_id.map(|id| id.to_string()).unwrap_or_default(),
+            e.email.clone().unwrap_or_default(),
+            e.ip.clone().unwrap_or_default(),
+            e.user_agent.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_csv_field_escaping() {
+        assert_eq!(csv_field("frodo@theshire.com"), "frodo@theshire.com");
+        assert_eq!(
+            csv_field("Mozilla/5.0 (X11, Linux)"),
+            "\"Mozilla/5.0 (X11, Linux)\""
+        );
+        assert_eq!(csv_field("say \"friend\""), "\"say \"\"friend\"\"\"");
+    }
+
+    #[test]
+    fn test_email_filter_matches_literally() {
+        assert_eq!(contains_pattern(r"a_b%c\d"), r"%a\_b\%c\\d%");
+        let prefix = uuid::Uuid::new_v4().simple().to_string();
+        let pool = testing::pool();
+        let conn = &mut connection_from(&pool).unwrap();
+        for address in ["a_b", "aXb"] {
+            insert_into(crate::schema::auth_events::table)
+                .values(NewAuthEvent {
+                    user_id: None,
+                    email: Some(format!("{prefix}{address}@theshire.com")),
+                    ip: None,
+                    user_agent: None,
+                    event: String::from("login"),
+                    outcome: String::from("failure"),
+                })
+                .execute(conn)
+                .unwrap();
+        }
+        let filter = AuthEventFilter {
+            email: Some(format!("{prefix}a_b")),
+            ..Default::default()
+        };
+        let events = search(&pool, &filter).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].email.as_deref(),
+            Some(format!("{prefix}a_b@theshire.com").as_str())
+        );
+    }
+
+    #[test]
+    fn test_last_day_filter() {
+        let until = NaiveDate::MAX.format("%Y-%m-%d").to_string();
+        let filter = AuthEventFilter {
+            since: Some(until.clone()),
+            until: Some(until),
+            ..Default::default()
+        };
+        assert_eq!(parse_date(&filter.until), Some(NaiveDate::MAX));
+        assert!(search(&testing::pool(), &filter).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_empty_filters_are_ignored() {
+        let filter = AuthEventFilter {
+            email: Some(String::from("  ")),
+            since: Some(String::from("not a date")),
+            ..Default::default()
+        };
+        assert_eq!(non_empty(&filter.email), None);
+        assert_eq!(parse_date(&filter.since), None);
+    }
+}
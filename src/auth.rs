@@ -0,0 +1,104 @@
+use crate::errors::ApiError;
+use crate::models::User;
+use crate::telemetry::record_user_id;
+use crate::{api_tokens, app_pool, current_user};
+use actix_identity::IdentityExt;
+use actix_web::{
+    dev::Payload,
+    http::{header, StatusCode},
+    web, FromRequest, HttpRequest,
+};
+use std::{future::Future, pin::Pin};
+
+///The authenticated user of a request, resolved either from an
+///`Authorization: Bearer` personal access token or from the session identity.
+pub struct CurrentUser {
+    pub user: User,
+    ///Scopes granted by the access token. Session users aren't limited by scopes.
+    pub scopes: Option<Vec<String>>,
+}
+
+impl CurrentUser {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|s| s == scope),
+            None => true,
+        }
+    }
+
+    ///Rejects token requests lacking the scope with a 403.
+    pub fn require_scope(&self, scope: &str) -> Result<(), ApiError> {
+        if self.has_scope(scope) {
+            return Ok(());
+        };
+        Err(ApiError::translated(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "api-error-missing_scope",
+            &[("scope", scope)],
+        ))
+    }
+
+    pub fn require_admin(&self) -> Result<(), ApiError> {
+        if self.user.is_admin() {
+            return Ok(());
+        };
+        Err(ApiError::translated(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "api-error-admin_required",
+            &[],
+        ))
+    }
+}
+
+///Resolves the user of a bearer token, or of the session when there is no
+///`Authorization` header. Malformed headers and unknown tokens yield `None`.
+async fn authenticate(req: &HttpRequest) -> Result<Option<CurrentUser>, ApiError> {
+    let pool = match app_pool(req) {
+        Some(pool) => pool,
+        None => {
+            log::error!("Error authenticating: the app has no database pool");
+            return Err(ApiError::internal());
+        }
+    };
+    if let Some(authorization) = req.headers().get(header::AUTHORIZATION) {
+        let token = match authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            Some(token) => token.trim().to_string(),
+            None => return Ok(None),
+        };
+        let found = match web::block(move || api_tokens::authenticate(&pool, &token)).await {
+            Ok(found) => found?,
+            Err(e) => {
+                log::error!("Error authenticating API token: {e}");
+                return Err(ApiError::internal());
+            }
+        };
+        return Ok(found.map(|(user, scopes)| CurrentUser {
+            user,
+            scopes: Some(scopes),
+        }));
+    };
+    let user = current_user(&pool, req.get_identity().ok()).await?;
+    Ok(user.map(|user| CurrentUser { user, scopes: None }))
+}
+
+impl FromRequest for CurrentUser {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let user = authenticate(&req)
+                .await?
+                .ok_or_else(ApiError::unauthorized)?;
+            record_user_id(user.user.id);
+            Ok(user)
+        })
+    }
+}
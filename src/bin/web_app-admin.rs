@@ -0,0 +1,311 @@
+//! Operator commands, run against the database in DATABASE_URL:
+//!
+//! ```text
+//! web_app-admin user create --email frodo@theshire.com --first-name Frodo --last-name Baggins
+//! web_app-admin user reset-password frodo@theshire.com
+//! web_app-admin sessions purge
+//! web_app-admin seed fixtures/dev.yaml
+//! ```
+//!
+//! Accounts go through the same services as the web app, so passwords are
+//! validated and hashed exactly like they are on registration.
+use clap::{builder::PossibleValuesParser, Args, Parser, Subcommand};
+use diesel::result::Error;
+use dotenvy::dotenv;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use web_app::models::{PasswordReset, User, UserRegistration};
+use web_app::repository::{DieselUserRepository, UserRepository};
+use web_app::services::users::{UserService, ROLES};
+use web_app::services::{auth::AuthService, ServiceError};
+use web_app::{fixtures, magic_link, migrations, pool, remember, telemetry};
+
+#[derive(Parser)]
+#[command(
+    name = "web_app-admin",
+    version,
+    about = "Manage web_app users, migrations and sessions"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    ///Manage user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    ///Run the pending database migrations
+    Migrate,
+    ///Manage what the server keeps of sign-ins
+    #[command(subcommand)]
+    Sessions(SessionsCommand),
+    ///Load fixture files (YAML or JSON) into the database. Users that already
+    ///exist are left alone, so seeding can be repeated. Fixture passwords are
+    ///public, never seed a production database.
+    Seed {
+        #[arg(default_value = fixtures::DEFAULT_FIXTURES)]
+        files: Vec<PathBuf>,
+    },
+}
+
+///Users are given by id or by email.
+#[derive(Subcommand)]
+enum UserCommand {
+    ///Create an account, prompting for its password
+    Create {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        first_name: String,
+        #[arg(long)]
+        last_name: String,
+        #[arg(long, default_value = "user", value_parser = PossibleValuesParser::new(ROLES))]
+        role: String,
+        #[command(flatten)]
+        password: PasswordInput,
+    },
+    ///List every account
+    List,
+    ///Show an account
+    Show { user: String },
+    ///Delete an account, along with its tokens and linked identities
+    Delete {
+        user: String,
+        ///Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    ///Set a new password and revoke the account's remember-me tokens
+    ResetPassword {
+        user: String,
+        #[command(flatten)]
+        password: PasswordInput,
+    },
+    ///Change the role of an account
+    SetRole {
+        user: String,
+        #[arg(value_parser = PossibleValuesParser::new(ROLES))]
+        role: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionsCommand {
+    ///Delete expired remember-me tokens and used or expired login links.
+    ///Sessions themselves live in signed cookies and can't be revoked by the
+    ///server, they end when the browser drops them.
+    Purge {
+        ///Also revoke every remember-me token still valid, signing everyone
+        ///out once their session cookie is gone
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Args)]
+struct PasswordInput {
+    ///Read the password from the first line of stdin instead of prompting
+    #[arg(long)]
+    password_stdin: bool,
+}
+
+type CommandResult = Result<(), String>;
+
+impl PasswordInput {
+    ///The password and its confirmation.
+    fn read(&self) -> io::Result<(String, String)> {
+        if self.password_stdin {
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            let password = line.trim_end_matches(['\r', '\n']).to_string();
+            return Ok((password.clone(), password));
+        };
+        let password = rpassword::prompt_password("Password: ")?;
+        let confirmation = rpassword::prompt_password("Confirm password: ")?;
+        Ok((password, confirmation))
+    }
+}
+
+fn describe(e: ServiceError) -> String {
+    let errors = e.into_validation_errors();
+    let mut messages = vec![];
+    for (field, field_errors) in errors.field_errors() {
+        for error in field_errors {
+            let message = error
+                .message
+                .as_deref()
+                .map(String::from)
+                .unwrap_or_else(|| error.code.to_string());
+            messages.push(format!("{field}: {message}"));
+        }
+    }
+    messages.join("\n")
+}
+
+fn database_error(e: Error) -> String {
+    format!("Database error: {e}")
+}
+
+///Looks the user up by id, or by email when the argument isn't a number.
+fn find_user(users: &UserService, user: &str) -> Result<User, String> {
+    let found = match user.parse::<i32>() {
+        Ok(user_id) => users.find(user_id),
+        Err(_) => users.find_by_email(user),
+    };
+    found.map_err(|e| match e {
+        Error::NotFound => format!("No user {user}"),
+        e => database_error(e),
+    })
+}
+
+fn print_user(user: &User) {
+    println!("id:         {}", user.id);
+    println!("email:      {}", user.email);
+    println!("name:       {} {}", user.first_name, user.last_name);
+    println!("role:       {}", user.role);
+    println!("created at: {}", user.created_at);
+    println!("updated at: {}", user.updated_at);
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+async fn user_command(command: UserCommand) -> CommandResult {
+    let repository: Arc<dyn UserRepository> = Arc::new(DieselUserRepository::new(pool().clone()));
+    let auth = AuthService::new(repository.clone());
+    let users = UserService::new(repository);
+    match command {
+        UserCommand::Create {
+            email,
+            first_name,
+            last_name,
+            role,
+            password,
+        } => {
+            let (password, confirmation) = password.read().map_err(|e| e.to_string())?;
+            let registration = UserRegistration {
+                first_name: Some(first_name),
+                last_name: Some(last_name),
+                email: Some(email),
+                _password: Some(password),
+                _confirm_password: Some(confirmation),
+            };
+            let mut user = auth.create_account(registration).await.map_err(describe)?;
+            if role != user.role {
+                user = users.set_role(user.id, &role).map_err(database_error)?;
+            };
+            println!("Created user {} ({})", user.id, user.email);
+        }
+        UserCommand::List => {
+            let all = users.list().map_err(database_error)?;
+            println!("{:>6}  {:<6}  {:<32}  NAME", "ID", "ROLE", "EMAIL");
+            for user in all {
+                println!(
+                    "{:>6}  {:<6}  {:<32}  {} {}",
+                    user.id, user.role, user.email, user.first_name, user.last_name
+                );
+            }
+        }
+        UserCommand::Show { user } => print_user(&find_user(&users, &user)?),
+        UserCommand::Delete { user, yes } => {
+            let user = find_user(&users, &user)?;
+            let question = format!("Delete user {} ({})?", user.id, user.email);
+            if !yes && !confirm(&question).map_err(|e| e.to_string())? {
+                return Err(String::from("Aborted"));
+            };
+            users.delete_user(user.id).map_err(database_error)?;
+            println!("Deleted user {} ({})", user.id, user.email);
+        }
+        UserCommand::ResetPassword { user, password } => {
+            let user = find_user(&users, &user)?;
+            let (password, confirmation) = password.read().map_err(|e| e.to_string())?;
+            let reset = PasswordReset {
+                _password: Some(password),
+                _confirm_password: Some(confirmation),
+            };
+            auth.reset_password(user.id, reset)
+                .await
+                .map_err(describe)?;
+            println!("Reset the password of user {} ({})", user.id, user.email);
+        }
+        UserCommand::SetRole { user, role } => {
+            let user = find_user(&users, &user)?;
+            let user = users.set_role(user.id, &role).map_err(database_error)?;
+            println!("User {} ({}) is now {}", user.id, user.email, user.role);
+        }
+    };
+    Ok(())
+}
+
+fn migrate() -> CommandResult {
+    let applied = migrations::run().map_err(|e| format!("Error running migrations: {e}"))?;
+    if applied.is_empty() {
+        println!("No pending migrations");
+    };
+    for version in applied {
+        println!("Applied {version}");
+    }
+    Ok(())
+}
+
+fn sessions_command(command: SessionsCommand) -> CommandResult {
+    match command {
+        SessionsCommand::Purge { all } => {
+            let pool = pool();
+            let tokens = match all {
+                true => remember::revoke_everyone(pool),
+                false => remember::purge_expired(pool),
+            };
+            let tokens = tokens.map_err(database_error)?;
+            let links = magic_link::purge(pool).map_err(database_error)?;
+            println!("Deleted {tokens} remember-me token(s) and {links} login link(s)");
+        }
+    };
+    Ok(())
+}
+
+fn seed(files: Vec<PathBuf>) -> CommandResult {
+    for file in files {
+        let loaded =
+            fixtures::load(&file).map_err(|e| format!("Error reading {}: {e}", file.display()))?;
+        let created = fixtures::seed(&DieselUserRepository::default(), &loaded)
+            .map_err(|e| format!("Error seeding {}: {e}", file.display()))?;
+        println!(
+            "{}: created {} of {} user(s)",
+            file.display(),
+            created.len(),
+            loaded.users.len()
+        );
+    }
+    Ok(())
+}
+
+//services run their blocking work on actix's thread pool, so they need its runtime
+#[actix_web::main]
+async fn main() -> ExitCode {
+    dotenv().ok();
+    let cli = Cli::parse();
+    telemetry::init();
+    let result = match cli.command {
+        Command::User(command) => user_command(command).await,
+        Command::Migrate => migrate(),
+        Command::Sessions(command) => sessions_command(command),
+        Command::Seed { files } => seed(files),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,508 @@
+use crate::i18n::{translate, Locale, DEFAULT_LOCALE};
+use crate::render;
+use crate::services::ServiceError;
+use crate::telemetry;
+use actix_web::{
+    body::{to_bytes, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    Error, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use actix_web_lab::middleware::Next;
+use derive_more::Display;
+use fluent_bundle::FluentArgs;
+use serde::Serialize;
+use serde_json::json;
+use tera::Context;
+use utoipa::ToSchema;
+use validator::ValidationErrors;
+
+///Error returned by the JSON API. Every API error serializes to the same
+///envelope:
+///
+///```json
+///{"error": {"status": 400, "code": "validation_failed", "message": "...", "fields": {...}}}
+///```
+///
+///`fields` is only present for validation errors and holds the
+///`validator::ValidationErrors` of the request, keyed by field name.
+///`request_id` matches the `X-Request-Id` header and the request's log lines.
+///
+///Messages are in English until the error is [localized](ApiError::localize)
+///for the request, which [`error_response`] and [`negotiate_errors`] do.
+#[derive(Clone, Debug, Display, Serialize)]
+#[display(fmt = "{}: {}", code, message)]
+pub struct ApiError {
+    #[serde(skip)]
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<ValidationErrors>,
+    ///Source of the message, for errors that can be translated.
+    #[serde(skip)]
+    message_id: Option<Box<MessageId>>,
+}
+
+///Fluent id and arguments of a message.
+#[derive(Clone, Debug)]
+struct MessageId {
+    id: &'static str,
+    args: Vec<(&'static str, String)>,
+}
+
+///OpenAPI description of the envelope [`ApiError`] serializes to.
+#[derive(ToSchema)]
+pub struct ErrorEnvelope {
+    pub error: ErrorBody,
+}
+
+#[derive(ToSchema)]
+pub struct ErrorBody {
+    #[schema(example = 400)]
+    pub status: u16,
+    #[schema(example = "validation_failed")]
+    pub code: String,
+    pub message: String,
+    ///Validation errors keyed by field name, each a list of
+    ///`{code, message, params}` objects.
+    #[schema(value_type = Option<Object>)]
+    pub fields: Option<ValidationErrors>,
+    #[schema(example = "3f0c9a62-1f1e-4c52-9d5e-2b8e4f7d6a10")]
+    pub request_id: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            fields: None,
+            message_id: None,
+        }
+    }
+
+    ///Error whose message is the Fluent message `id`.
+    pub fn translated(
+        status: StatusCode,
+        code: &'static str,
+        id: &'static str,
+        args: &[(&'static str, &str)],
+    ) -> ApiError {
+        let args: Vec<_> = args
+            .iter()
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect();
+        ApiError {
+            message: translate(DEFAULT_LOCALE, id, Some(&fluent_args(&args))),
+            message_id: Some(Box::new(MessageId { id, args })),
+            ..ApiError::new(status, code, "")
+        }
+    }
+
+    ///Translates the message and the validation errors into the locale.
+    pub fn localize(mut self, locale: Locale) -> ApiError {
+        if let Some(MessageId { id, args }) = self.message_id.as_deref() {
+            self.message = translate(locale.as_str(), id, Some(&fluent_args(args)));
+        };
+        self.fields = self.fields.map(|fields| locale.translate_errors(&fields));
+        self
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn unauthorized() -> ApiError {
+        ApiError::translated(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "api-error-unauthorized",
+            &[],
+        )
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn not_found() -> ApiError {
+        ApiError::translated(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "api-error-not_found",
+            &[],
+        )
+    }
+
+    pub fn method_not_allowed() -> ApiError {
+        ApiError::translated(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method_not_allowed",
+            "api-error-method_not_allowed",
+            &[],
+        )
+    }
+
+    pub fn unavailable() -> ApiError {
+        ApiError::translated(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "service_unavailable",
+            "error-unavailable",
+            &[],
+        )
+    }
+
+    pub fn internal() -> ApiError {
+        ApiError::translated(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "error-internal_error",
+            &[],
+        )
+    }
+}
+
+fn fluent_args<'a>(args: &'a [(&'static str, String)]) -> FluentArgs<'a> {
+    let mut fluent_args = FluentArgs::new();
+    for (name, value) in args {
+        fluent_args.set(*name, value.as_str());
+    }
+    fluent_args
+}
+
+impl ApiError {
+    ///Error with a code matching the status, e.g. a 404 gets `not_found`.
+    pub fn from_status(status: StatusCode, message: impl Into<String>) -> ApiError {
+        let code = match status {
+            StatusCode::BAD_REQUEST => "bad_request",
+            StatusCode::UNAUTHORIZED => "unauthorized",
+            StatusCode::FORBIDDEN => "forbidden",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+            StatusCode::SERVICE_UNAVAILABLE => "service_unavailable",
+            status if status.is_server_error() => "internal_error",
+            _ => "error",
+        };
+        ApiError::new(status, code, message)
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        ApiError {
+            fields: Some(errors),
+            ..ApiError::translated(
+                StatusCode::BAD_REQUEST,
+                "validation_failed",
+                "api-error-validation_failed",
+                &[],
+            )
+        }
+    }
+}
+
+impl From<ServiceError> for ApiError {
+    fn from(e: ServiceError) -> Self {
+        match e {
+            ServiceError::Invalid(errors) => ApiError::from(errors),
+            ServiceError::Unavailable => ApiError::unavailable(),
+            ServiceError::Internal => ApiError::internal(),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            diesel::result::Error::NotFound => ApiError::not_found(),
+            //no connection could be checked out, see `crate::connect`
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::ClosedConnection,
+                _,
+            ) => {
+                log::error!("Database unavailable: {e}");
+                ApiError::unavailable()
+            }
+            e => {
+                log::error!("Database error: {e}");
+                ApiError::internal()
+            }
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut error = serde_json::to_value(self).unwrap();
+        error["status"] = json!(self.status.as_u16());
+        if let Some(request_id) = telemetry::current_request_id() {
+            error["request_id"] = json!(request_id);
+        };
+        let mut response = HttpResponse::build(self.status);
+        if self.status == StatusCode::UNAUTHORIZED {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        };
+        response
+            .content_type("application/json; charset=utf-8")
+            .body(json!({ "error": error }).to_string())
+    }
+}
+
+///Whether the error should be answered with JSON rather than an HTML page:
+///always under `/api/`, otherwise when `Accept` ranks JSON above HTML. Clients
+///without a preference (e.g. `fetch`, which sends `*/*`) get JSON when they
+///sent JSON.
+pub fn wants_json(req: &HttpRequest) -> bool {
+    if req.path().starts_with("/api/") {
+        return true;
+    };
+    accepts_json_first(req).unwrap_or_else(|| req.content_type() == "application/json")
+}
+
+///Whether `Accept` ranks JSON above HTML, if it names either.
+fn accepts_json_first(req: &HttpRequest) -> Option<bool> {
+    let accept = req.get_header::<header::Accept>()?;
+    accept
+        .ranked()
+        .iter()
+        .find_map(|mime| match mime.essence_str() {
+            "text/html" => Some(false),
+            "application/json" => Some(true),
+            _ => None,
+        })
+}
+
+///Answers with the JSON envelope or an error page, whichever the request
+///accepts.
+pub fn error_response(req: &HttpRequest, error: ApiError) -> HttpResponse {
+    let error = error.localize(Locale::negotiate(req));
+    if wants_json(req) {
+        return error.error_response();
+    };
+    let mut context = Context::new();
+    context.insert("title", error.status.canonical_reason().unwrap_or("Error"));
+    context.insert("status", &error.status.as_u16());
+    context.insert("message", &error.message);
+    context.insert("lang", &Locale::negotiate(req));
+    context.insert("request_id", &telemetry::request_id(req));
+    let mut response = render("error.html", context);
+    *response.status_mut() = error.status;
+    response
+}
+
+pub async fn not_found(req: HttpRequest) -> HttpResponse {
+    error_response(
+        &req,
+        ApiError::translated(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "api-error-page_not_found",
+            &[],
+        ),
+    )
+}
+
+pub fn method_not_allowed(req: &HttpRequest, allow: &str) -> HttpResponse {
+    let mut response = error_response(req, ApiError::method_not_allowed());
+    if let Ok(allow) = HeaderValue::from_str(allow) {
+        response.headers_mut().insert(header::ALLOW, allow);
+    };
+    response
+}
+
+///Negotiates error responses that don't have a JSON or HTML body yet, such as
+///actix's plain text extractor errors, and translates [`ApiError`]s returned by
+///handlers and extractors. [`ApiError`]s stay JSON unless `Accept` ranks HTML
+///first, as browsers do, outside of `/api/`. Headers like `Allow` and
+///`WWW-Authenticate` are kept; 5xx details are never shown to the client.
+pub async fn negotiate_errors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let res = next.call(req).await?;
+    let api_error = res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<ApiError>())
+        .cloned();
+    if let Some(api_error) = api_error {
+        let locale = Locale::negotiate(res.request());
+        let html = !res.request().path().starts_with("/api/")
+            && accepts_json_first(res.request()) == Some(false);
+        if locale == Locale::default() && !html {
+            return Ok(res.map_into_left_body());
+        };
+        let (req, res) = res.into_parts();
+        let mut response = if html {
+            error_response(&req, api_error)
+        } else {
+            api_error.localize(locale).error_response()
+        };
+        for (name, value) in res.headers().iter() {
+            if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+                response.headers_mut().insert(name.clone(), value.clone());
+            };
+        }
+        return Ok(ServiceResponse::new(req, response).map_into_right_body());
+    };
+    let status = res.status();
+    let negotiated = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| {
+            content_type.starts_with("text/html") || content_type.starts_with("application/json")
+        });
+    if negotiated || !(status.is_client_error() || status.is_server_error()) {
+        return Ok(res.map_into_left_body());
+    };
+    let (req, res) = res.into_parts();
+    let headers = res.headers().clone();
+    let body = to_bytes(res.into_body()).await.ok();
+    let message = match body {
+        Some(body) if !body.is_empty() && status.is_client_error() => {
+            String::from_utf8_lossy(&body).into_owned()
+        }
+        _ => String::from(status.canonical_reason().unwrap_or("Error")),
+    };
+    let mut response = error_response(&req, ApiError::from_status(status, message));
+    for (name, value) in headers.iter() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            response.headers_mut().append(name.clone(), value.clone());
+        };
+    }
+    Ok(ServiceResponse::new(req, response).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use actix_web::{test, web, App};
+    use actix_web_lab::middleware::from_fn;
+    use validator::ValidationError;
+
+    #[actix_web::test]
+    async fn test_errors_follow_the_locale() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(negotiate_errors))
+                .route(
+                    "/me",
+                    web::get().to(|| async { Err::<HttpResponse, _>(ApiError::unauthorized()) }),
+                )
+                .route(
+                    "/users",
+                    web::post().to(|| async {
+                        let mut errors = ValidationErrors::new();
+                        errors.add("email", ValidationError::new("blank"));
+                        Err::<HttpResponse, _>(ApiError::from(errors))
+                    }),
+                )
+                .default_service(web::to(not_found)),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri("/me")
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+            "Bearer"
+        );
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "unauthorized");
+        assert_eq!(body["error"]["message"], "Authentification requise");
+
+        let request = test::TestRequest::post()
+            .uri("/users")
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(
+            body["error"]["message"],
+            "La requête n'a pas passé la validation"
+        );
+        assert_eq!(
+            body["error"]["fields"]["email"][0]["message"],
+            "Obligatoire"
+        );
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/nothing")
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["error"]["message"], "Page introuvable");
+
+        let request = test::TestRequest::get().uri("/me").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["error"]["message"], "Authentication required");
+
+        for (language, message) in [
+            ("en", "Authentication required"),
+            ("fr", "Authentification requise"),
+        ] {
+            let request = test::TestRequest::get()
+                .uri("/me")
+                .insert_header((header::ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8"))
+                .insert_header((header::ACCEPT_LANGUAGE, language))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), 401);
+            assert_eq!(
+                response.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+                "Bearer"
+            );
+            assert_eq!(
+                response.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/html; charset=utf-8"
+            );
+            let body = test::read_body(response).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            assert!(body.contains(message));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_validation_error_envelope() {
+        let mut errors = ValidationErrors::new();
+        errors.add("email", ValidationError::new("email"));
+        let response = ApiError::from(errors).error_response();
+        assert_eq!(response.status(), 400);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "error": {
+                    "status": 400,
+                    "code": "validation_failed",
+                    "message": "The request failed validation",
+                    "fields": { "email": [{ "code": "email", "message": null, "params": {} }] }
+                }
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_unreachable_database_is_unavailable() {
+        let closed = diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::ClosedConnection,
+            Box::new(String::from("timed out waiting for connection")),
+        );
+        assert_eq!(ApiError::from(closed).status, 503);
+        assert_eq!(ApiError::from(diesel::result::Error::NotFound).status, 404);
+    }
+}
@@ -0,0 +1,121 @@
+//! Data put in a database on purpose rather than by migrations, so no
+//! environment gets an account it didn't ask for. Development data lives in
+//! fixture files under `fixtures/` (YAML or JSON) and is loaded with
+//! `web_app-admin seed`; tests create the users they need with
+//! `testing::create_user`.
+use crate::models::{NewUser, User};
+use crate::repository::UserRepository;
+use crate::services::auth::password_hasher;
+use diesel::result::Error;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+pub type FixtureResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+///Fixture file loaded by `web_app-admin seed` when no file is given.
+pub const DEFAULT_FIXTURES: &str = "fixtures/dev.yaml";
+
+///Contents of a fixture file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixtures {
+    #[serde(default)]
+    pub users: Vec<UserFixture>,
+}
+
+///A user to create, with its password in plain text. Fixture passwords skip
+///the registration rules, they're only meant for development databases.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserFixture {
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub password: String,
+    #[serde(default = "default_role")]
+    pub role: String,
+}
+
+fn default_role() -> String {
+    String::from("user")
+}
+
+///Reads a fixture file, parsed as JSON when it ends in `.json` and as YAML
+///otherwise.
+pub fn load(path: &Path) -> FixtureResult<Fixtures> {
+    let contents = fs::read_to_string(path)?;
+    let fixtures = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => serde_json::from_str(&contents)?,
+        _ => serde_yaml::from_str(&contents)?,
+    };
+    Ok(fixtures)
+}
+
+///Creates the user, hashing its password like registration does.
+pub fn insert_user(users: &dyn UserRepository, fixture: &UserFixture) -> FixtureResult<User> {
+    let new_user = NewUser {
+        first_name: fixture.first_name.clone(),
+        last_name: fixture.last_name.clone(),
+        email: fixture.email.clone(),
+        password: password_hasher(&fixture.password)
+            .map_err(|e| format!("Error hashing password: {e}"))?,
+    };
+    let mut user = users.create(new_user)?;
+    if user.role != fixture.role {
+        user = users.set_role(user.id, &fixture.role)?;
+    };
+    Ok(user)
+}
+
+///Creates the fixtures' users, skipping the ones whose email is already
+///registered so seeding can be repeated. Returns the users created.
+pub fn seed(users: &dyn UserRepository, fixtures: &Fixtures) -> FixtureResult<Vec<User>> {
+    let mut created = vec![];
+    for fixture in &fixtures.users {
+        match users.find_by_email(&fixture.email) {
+            Ok(_) => continue,
+            Err(Error::NotFound) => created.push(insert_user(users, fixture)?),
+            Err(e) => return Err(e.into()),
+        };
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::password_hash_checker;
+    use crate::repository::InMemoryUserRepository;
+
+    #[test]
+    fn test_fixture_files_parse() {
+        let fixtures = load(Path::new(DEFAULT_FIXTURES)).unwrap();
+        assert!(!fixtures.users.is_empty());
+        let fixtures: Fixtures = serde_json::from_str(
+            r#"{"users": [{"first_name": "Sam", "last_name": "Gamgee", "email": "sam@theshire.com", "password": "Password1!"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(fixtures.users[0].role, "user");
+        assert!(serde_yaml::from_str::<Fixtures>("groups: []").is_err());
+    }
+
+    #[test]
+    fn test_seeding_is_repeatable() {
+        let users = InMemoryUserRepository::default();
+        let fixtures: Fixtures = serde_yaml::from_str(
+            "users:
+  - first_name: Gandalf
+    last_name: Grey
+    email: gandalf@valinor.com
+    password: Mellon1!
+    role: admin",
+        )
+        .unwrap();
+        let created = seed(&users, &fixtures).unwrap();
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].role, "admin");
+        assert!(password_hash_checker("Mellon1!", &created[0].password).is_ok());
+        assert!(seed(&users, &fixtures).unwrap().is_empty());
+    }
+}
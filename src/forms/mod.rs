@@ -1,82 +1,302 @@
+//! Declarative HTML forms. [`Form::fields_of`] takes the fields of a request
+//! struct from its OpenAPI schema, which mirrors the struct's `validator`
+//! constraints, so browsers check the same required fields, lengths and
+//! patterns the server does. Forms render with the macros in
+//! `templates/form_macros.html`.
+use crate::i18n::{lookup, Locale};
+use crate::models::{MagicLinkRequest, UserLogin, UserRegistration};
 use chrono::Datelike;
 use serde::Serialize;
+use serde_json::Value;
+use utoipa::openapi::{KnownFormat, Object, RefOr, Schema, SchemaFormat, SchemaType};
+use utoipa::ToSchema;
+use validator::ValidationErrors;
+
+pub const MAGIC_LINK_TITLE: &str = "Email Login Link";
+
+///What a form is for, so templates can add what belongs with it, e.g. the
+///other ways to log in next to the login form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormKind {
+    Login,
+    Register,
+    MagicLink,
+    MagicLinkConfirm,
+}
 
 #[derive(Serialize)]
-pub struct LogRegForm {
+pub struct Form {
+    kind: FormKind,
+    ///Names the page in English, whatever the language it is shown in.
     title: String,
+    ///The title shown to the user, in their language.
+    heading: String,
+    lang: Locale,
     action: String,
     method: String,
-    fields: Vec<LogRegFormField>,
+    fields: Vec<Field>,
     year: i32,
     home: String,
+    ///Messages of errors that aren't about a single field.
+    errors: Vec<String>,
 }
 
 #[derive(Serialize)]
-struct LogRegFormField {
-    id: String,
-    text: String,
-    field_type: String,
-    placeholder: String,
+pub struct Field {
+    pub id: String,
+    pub label: String,
+    pub input_type: String,
+    pub placeholder: String,
+    pub required: bool,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub pattern: Option<String>,
+    pub value: Option<String>,
+    pub errors: Vec<String>,
 }
 
-impl LogRegFormField {
-    pub fn new(id: &str, text: &str, field_type: &str, placeholder: &str) -> LogRegFormField {
-        LogRegFormField {
+///`first_name` becomes `First Name`.
+fn humanize(id: &str) -> String {
+    id.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+impl Field {
+    pub fn new(id: &str, label: &str, input_type: &str) -> Field {
+        Field {
             id: String::from(id),
-            text: String::from(text),
-            field_type: String::from(field_type),
-            placeholder: String::from(placeholder),
+            label: String::from(label),
+            input_type: String::from(input_type),
+            placeholder: String::from(label),
+            required: false,
+            min_length: None,
+            max_length: None,
+            pattern: None,
+            value: None,
+            errors: vec![],
         }
     }
-}
 
-impl LogRegForm {
-    pub fn new(title: &str, action: &str, method: &str) -> LogRegForm {
-        let mut form_fields = vec![
-            LogRegFormField::new("email", "Email", "email", "Please enter a valid email."),
-            LogRegFormField::new(
-                "password",
-                "Password",
-                "password",
-                "Please enter a valid password.",
-            ),
-        ];
-        if title == "Register" {
-            form_fields.insert(
-                0,
-                LogRegFormField::new(
-                    "first_name",
-                    "First Name",
-                    "first_name",
-                    "Please enter your first name.",
-                ),
-            );
-            form_fields.insert(
-                1,
-                LogRegFormField::new(
-                    "last_name",
-                    "Last Name",
-                    "last_name",
-                    "Please enter your last name.",
-                ),
-            );
-            form_fields.push(LogRegFormField::new(
-                "confirm_password",
-                "Confirm Password",
-                "password",
-                "Please confirm your password.",
-            ));
+    fn from_schema(id: &str, schema: &Object, required: bool) -> Field {
+        let input_type = match (&schema.schema_type, &schema.format) {
+            (SchemaType::Boolean, _) => "checkbox",
+            (SchemaType::Integer | SchemaType::Number, _) => "number",
+            (_, Some(SchemaFormat::KnownFormat(KnownFormat::Password))) => "password",
+            (_, Some(SchemaFormat::Custom(format))) if format == "email" => "email",
+            _ => "text",
         };
-        let year = chrono::Utc::now().year();
+        let label = schema.title.clone().unwrap_or_else(|| humanize(id));
+        let mut field = Field::new(id, &label, input_type);
+        field.required = required && input_type != "checkbox";
+        field.min_length = schema.min_length;
+        field.max_length = schema.max_length;
+        field.pattern = schema.pattern.clone();
+        field
+    }
+}
 
-        LogRegForm {
+impl Form {
+    pub fn new(kind: FormKind, title: &str, action: &str, method: &str) -> Form {
+        Form {
+            kind,
             title: String::from(title),
+            heading: String::from(title),
+            lang: Locale::default(),
             action: String::from(action),
             method: String::from(method),
-            year,
-            fields: form_fields,
+            fields: vec![],
+            year: chrono::Utc::now().year(),
             //TODO create Login / Register redirect...
             home: String::from("/home"),
+            errors: vec![],
         }
     }
+
+    ///Adds a field for every property of the struct's schema, in declaration
+    ///order.
+    pub fn fields_of<'s, T: ToSchema<'s>>(mut self) -> Form {
+        if let (_, RefOr::T(Schema::Object(object))) = T::schema() {
+            for (id, property) in object.properties.iter() {
+                if let RefOr::T(Schema::Object(property)) = property {
+                    let required = object.required.contains(id);
+                    self.fields.push(Field::from_schema(id, property, required));
+                };
+            }
+        };
+        self
+    }
+
+    pub fn field(mut self, field: Field) -> Form {
+        self.fields.push(field);
+        self
+    }
+
+    pub fn label(mut self, id: &str, label: &str) -> Form {
+        if let Some(field) = self.fields.iter_mut().find(|field| field.id == id) {
+            field.label = String::from(label);
+        };
+        self
+    }
+
+    ///Sets the field's placeholder, which also serves as its error message for
+    ///errors that don't have one.
+    pub fn placeholder(mut self, id: &str, placeholder: &str) -> Form {
+        if let Some(field) = self.fields.iter_mut().find(|field| field.id == id) {
+            field.placeholder = String::from(placeholder);
+        };
+        self
+    }
+
+    pub fn without(mut self, id: &str) -> Form {
+        self.fields.retain(|field| field.id != id);
+        self
+    }
+
+    ///Translates the heading from the `<name>-title` message, and the labels
+    ///and placeholders of fields from the `field-<id>` messages, where the
+    ///locale or English has them.
+    pub fn localize(mut self, locale: Locale, name: &str) -> Form {
+        let lang = locale.as_str();
+        self.lang = locale;
+        if let Some(heading) = lookup(lang, &format!("{name}-title"), None) {
+            self.heading = heading;
+        };
+        for field in self.fields.iter_mut() {
+            if let Some(label) = lookup(lang, &format!("field-{}", field.id), None) {
+                field.label = label;
+            };
+            if let Some(placeholder) =
+                lookup(lang, &format!("field-{}.placeholder", field.id), None)
+            {
+                field.placeholder = placeholder;
+            };
+        }
+        self
+    }
+
+    ///Fills in the submitted values so a failed form post can be shown again.
+    ///Passwords are never sent back.
+    pub fn with_values(mut self, values: &impl Serialize) -> Form {
+        let values = serde_json::to_value(values).unwrap_or_default();
+        for field in self.fields.iter_mut() {
+            if field.input_type == "password" {
+                continue;
+            };
+            field.value = match &values[&field.id] {
+                Value::String(value) => Some(value.clone()),
+                Value::Number(value) => Some(value.to_string()),
+                Value::Bool(true) => Some(String::from("on")),
+                _ => None,
+            };
+        }
+        self
+    }
+
+    ///Attaches each error's message to its field, or to the form for errors
+    ///that aren't about a field. Errors without a message fall back to the
+    ///field's placeholder, which describes what's expected.
+    pub fn with_errors(mut self, errors: &ValidationErrors) -> Form {
+        for (field_id, field_errors) in errors.field_errors() {
+            let field = self.fields.iter_mut().find(|field| field.id == field_id);
+            let fallback = field.as_ref().map(|field| field.placeholder.clone());
+            let messages: Vec<String> = field_errors
+                .iter()
+                .filter_map(|e| {
+                    e.message
+                        .as_ref()
+                        .map(|message| message.to_string())
+                        .or_else(|| fallback.clone())
+                })
+                .collect();
+            match field {
+                Some(field) => field.errors.extend(messages),
+                None => self.errors.extend(messages),
+            };
+        }
+        self.errors.dedup();
+        self
+    }
+}
+
+pub fn login_form(locale: Locale) -> Form {
+    Form::new(FormKind::Login, "Log In", "/login", "POST")
+        .fields_of::<UserLogin>()
+        .localize(locale, "login")
+}
+
+pub fn register_form(locale: Locale) -> Form {
+    Form::new(FormKind::Register, "Register", "/register", "POST")
+        .fields_of::<UserRegistration>()
+        .localize(locale, "register")
+}
+
+pub fn magic_link_form(locale: Locale) -> Form {
+    Form::new(
+        FormKind::MagicLink,
+        MAGIC_LINK_TITLE,
+        "/login/magic",
+        "POST",
+    )
+    .fields_of::<MagicLinkRequest>()
+    .localize(locale, "login-magic")
+}
+
+///Confirms a login link before it's used. Opening the link only shows this
+///form, so mail scanners fetching it don't use it up.
+pub fn magic_link_confirm_form(locale: Locale, token: &str) -> Form {
+    Form::new(
+        FormKind::MagicLinkConfirm,
+        MAGIC_LINK_TITLE,
+        &format!("/login/magic/{token}"),
+        "POST",
+    )
+    .localize(locale, "login-magic-confirm")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PASSWORD_PATTERN;
+
+    #[test]
+    fn test_fields_mirror_validation() {
+        let form = register_form(Locale::default());
+        let ids: Vec<&str> = form.fields.iter().map(|field| field.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "first_name",
+                "last_name",
+                "email",
+                "password",
+                "confirm_password"
+            ]
+        );
+        let password = &form.fields[3];
+        assert_eq!(password.input_type, "password");
+        assert_eq!(password.min_length, Some(8));
+        assert!(password.required);
+        assert_eq!(password.pattern.as_deref(), Some(PASSWORD_PATTERN.as_str()));
+        assert_eq!(form.kind, FormKind::Register);
+        assert_eq!(form.fields[1].label, "Last Name");
+        assert_eq!(form.fields[2].input_type, "email");
+
+        let remember = login_form(Locale::default()).fields.pop().unwrap();
+        assert_eq!(remember.input_type, "checkbox");
+        assert!(!remember.required);
+        assert_eq!(remember.label, "Remember me");
+
+        let form = login_form(Locale::new("fr").unwrap());
+        assert_eq!(form.heading, "Connexion");
+        assert_eq!(form.fields[1].label, "Mot de passe");
+    }
 }
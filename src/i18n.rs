@@ -0,0 +1,244 @@
+//! Localization of UI strings and validation messages with Fluent. Messages
+//! live in `locales/<lang>/main.ftl`, and English is the fallback for anything
+//! a locale doesn't translate. Validation errors are translated by code, from
+//! the `error-<code>` messages.
+use actix_web::{dev::Payload, http::header, FromRequest, HttpRequest};
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource, FluentValue};
+use fluent_langneg::{accepted_languages, negotiate_languages, NegotiationStrategy};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use unic_langid::LanguageIdentifier;
+use validator::ValidationErrors;
+
+pub const DEFAULT_LOCALE: &str = "en";
+///Cookie holding the locale the user picked, which wins over `Accept-Language`.
+pub const LOCALE_COOKIE: &str = "lang";
+
+const RESOURCES: [(&str, &str); 2] = [
+    ("en", include_str!("../locales/en/main.ftl")),
+    ("fr", include_str!("../locales/fr/main.ftl")),
+];
+
+lazy_static! {
+    static ref BUNDLES: HashMap<&'static str, FluentBundle<FluentResource>> = RESOURCES
+        .iter()
+        .map(|(lang, source)| {
+            let resource = FluentResource::try_new(source.to_string())
+                .unwrap_or_else(|(_, e)| panic!("Invalid {lang} messages: {e:?}"));
+            let mut bundle = FluentBundle::new_concurrent(vec![lang.parse().unwrap()]);
+            //the isolation marks Fluent puts around arguments end up in form values
+            bundle.set_use_isolating(false);
+            bundle.add_resource(resource).unwrap();
+            (*lang, bundle)
+        })
+        .collect();
+    static ref AVAILABLE: Vec<LanguageIdentifier> = RESOURCES
+        .iter()
+        .map(|(lang, _)| lang.parse().unwrap())
+        .collect();
+}
+
+///Formats a message, or one of its attributes when the id is written
+///`message.attribute`.
+fn format(lang: &str, id: &str, args: Option<&FluentArgs>) -> Option<String> {
+    let bundle = BUNDLES.get(lang)?;
+    let (message_id, attribute) = match id.split_once('.') {
+        Some((message_id, attribute)) => (message_id, Some(attribute)),
+        None => (id, None),
+    };
+    let message = bundle.get_message(message_id)?;
+    let pattern = match attribute {
+        Some(attribute) => message.get_attribute(attribute)?.value(),
+        None => message.value()?,
+    };
+    let mut errors = vec![];
+    let text = bundle.format_pattern(pattern, args, &mut errors);
+    if !errors.is_empty() {
+        log::warn!("Error formatting {id} in {lang}: {errors:?}");
+    };
+    Some(text.into_owned())
+}
+
+///Formats a message in the locale, falling back to English.
+pub fn lookup(lang: &str, id: &str, args: Option<&FluentArgs>) -> Option<String> {
+    format(lang, id, args).or_else(|| format(DEFAULT_LOCALE, id, args))
+}
+
+///Like [`lookup`], but unknown ids come back as they are, so a missing message
+///shows up on the page instead of leaving a blank.
+pub fn translate(lang: &str, id: &str, args: Option<&FluentArgs>) -> String {
+    lookup(lang, id, args).unwrap_or_else(|| id.to_string())
+}
+
+fn fluent_value(value: &Value) -> Option<FluentValue<'static>> {
+    match value {
+        Value::String(value) => Some(FluentValue::from(value.clone())),
+        Value::Number(value) => value.as_f64().map(FluentValue::from),
+        _ => None,
+    }
+}
+
+///The `t()` template function: `{{ t(key="back", lang=lang) }}`. Any other
+///arguments are passed on to the message.
+pub fn tera_translate(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let id = args
+        .get("key")
+        .and_then(Value::as_str)
+        .ok_or_else(|| tera::Error::msg("t() needs a `key` argument"))?;
+    let lang = args
+        .get("lang")
+        .and_then(Value::as_str)
+        .unwrap_or(DEFAULT_LOCALE);
+    let mut fluent_args = FluentArgs::new();
+    for (name, value) in args {
+        if name == "key" || name == "lang" {
+            continue;
+        };
+        if let Some(value) = fluent_value(value) {
+            fluent_args.set(name.clone(), value);
+        };
+    }
+    Ok(Value::String(translate(lang, id, Some(&fluent_args))))
+}
+
+///The locale a request is answered in: the user's pick from the locale cookie,
+///otherwise the best match for `Accept-Language`, otherwise English.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Locale(&'static str);
+
+impl Default for Locale {
+    fn default() -> Locale {
+        Locale(DEFAULT_LOCALE)
+    }
+}
+
+impl Locale {
+    ///The shipped locale with the tag, if there is one.
+    pub fn new(lang: &str) -> Option<Locale> {
+        RESOURCES
+            .iter()
+            .find(|(tag, _)| tag.eq_ignore_ascii_case(lang))
+            .map(|(tag, _)| Locale(tag))
+    }
+
+    pub fn negotiate(req: &HttpRequest) -> Locale {
+        if let Some(locale) = req
+            .cookie(LOCALE_COOKIE)
+            .and_then(|cookie| Locale::new(cookie.value()))
+        {
+            return locale;
+        };
+        let requested = req
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .map(accepted_languages::parse)
+            .unwrap_or_default();
+        negotiate_languages(&requested, &AVAILABLE, None, NegotiationStrategy::Filtering)
+            .first()
+            .and_then(|lang| Locale::new(&lang.to_string()))
+            .unwrap_or_default()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+
+    pub fn t(&self, id: &str) -> String {
+        translate(self.0, id, None)
+    }
+
+    ///Replaces the messages of errors with a translated `error-<code>`
+    ///message, passing the error's params (e.g. `min`) as arguments. Errors
+    ///without one keep their message.
+    pub fn translate_errors(&self, errors: &ValidationErrors) -> ValidationErrors {
+        let mut translated = ValidationErrors::new();
+        for (field, field_errors) in errors.field_errors() {
+            for error in field_errors {
+                let mut error = error.clone();
+                let mut args = FluentArgs::new();
+                for (name, value) in error.params.iter() {
+                    if let Some(value) = fluent_value(value) {
+                        args.set(name.clone(), value);
+                    };
+                }
+                if let Some(message) = lookup(self.0, &format!("error-{}", error.code), Some(&args))
+                {
+                    error.message = Some(Cow::Owned(message));
+                };
+                translated.add(field, error);
+            }
+        }
+        translated
+    }
+}
+
+impl FromRequest for Locale {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Locale::negotiate(req)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::error;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_negotiates_locale() {
+        let req = TestRequest::default()
+            .insert_header((header::ACCEPT_LANGUAGE, "de-DE, fr-CA;q=0.8, en;q=0.5"))
+            .to_http_request();
+        assert_eq!(Locale::negotiate(&req).as_str(), "fr");
+
+        let req = TestRequest::default()
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .cookie(actix_web::cookie::Cookie::new(LOCALE_COOKIE, "en"))
+            .to_http_request();
+        assert_eq!(Locale::negotiate(&req).as_str(), "en");
+
+        let req = TestRequest::default()
+            .insert_header((header::ACCEPT_LANGUAGE, "de"))
+            .to_http_request();
+        assert_eq!(Locale::negotiate(&req), Locale::default());
+    }
+
+    #[test]
+    fn test_translates_errors_by_code() {
+        let mut errors = ValidationErrors::new();
+        let mut length = error("password_length", "Password must be at least 8 characters");
+        length.add_param(Cow::Borrowed("min"), &8);
+        errors.add("password", length);
+        errors.add("email", error("unknown_code", "Kept as is"));
+
+        let french = Locale::new("fr").unwrap().translate_errors(&errors);
+        let messages: Vec<_> = ["password", "email"]
+            .iter()
+            .map(|field| french.field_errors()[field][0].message.clone().unwrap())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "Le mot de passe doit contenir au moins 8 caractères",
+                "Kept as is"
+            ]
+        );
+        assert_eq!(
+            Locale::new("fr").unwrap().t("field-email.placeholder"),
+            "Veuillez saisir une adresse e-mail valide."
+        );
+        assert_eq!(
+            Locale::new("fr").unwrap().t("missing-message"),
+            "missing-message"
+        );
+    }
+}
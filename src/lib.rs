@@ -1,33 +1,130 @@
+pub mod api_tokens;
+pub mod app;
+pub mod audit;
+pub mod auth;
+pub mod errors;
+pub mod fixtures;
 pub mod forms;
+pub mod i18n;
+pub mod magic_link;
+pub mod mailer;
+pub mod metrics;
+pub mod migrations;
 pub mod models;
+pub mod oidc;
+pub mod proxy;
+pub mod remember;
+pub mod repository;
 pub mod routes;
+pub mod routing;
 pub mod schema;
-use actix_web::{http::StatusCode, HttpResponse, Responder};
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
+pub mod services;
+pub mod shutdown;
+pub mod telemetry;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod tls;
+pub mod tokens;
+pub mod validation;
+pub mod workers;
+use actix_identity::Identity;
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use diesel::{
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool, PoolError, PooledConnection},
+    result::{DatabaseErrorKind, Error},
+    OptionalExtension, QueryResult,
 };
-use diesel::{insert_into, pg::PgConnection, prelude::*};
-use dotenvy::dotenv;
+use errors::ApiError;
 use lazy_static::lazy_static;
-use models::{NewUser, UserRegistration};
-use std::borrow::Cow;
+use models::User;
 use std::env;
+use std::time::{Duration, Instant};
 use tera::{Context, Tera};
-use validator::ValidationError;
 
 lazy_static! {
-    static ref TEMPLATES: Tera = Tera::new("templates/*").unwrap();
+    static ref TEMPLATES: Tera = {
+        let mut tera = Tera::new("templates/*").unwrap();
+        tera.register_function("t", i18n::tera_translate);
+        tera
+    };
+    static ref POOL: DbPool = Pool::builder()
+        .max_size(pool_size())
+        .connection_timeout(Duration::from_secs(5))
+        //connect lazily, so the app starts (and reports unready) without a database
+        .build_unchecked(ConnectionManager::new(database_url()));
     pub static ref JSON: &'static str = "application/json";
     pub static ref HTML: &'static str = "text/html";
 }
 
-pub fn establish_connection() -> PgConnection {
-    dotenv().ok();
+#[cfg(not(test))]
+pub fn database_url() -> String {
+    dotenvy::dotenv().ok();
+
+    env::var("DATABASE_URL").expect("DATABASE_URL must be set")
+}
+
+///Tests run against a database of their own, see [`testing`].
+#[cfg(test)]
+pub fn database_url() -> String {
+    testing::database_url()
+}
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+///Size of the connection pool, from DATABASE_POOL_SIZE. Defaults to 10.
+fn pool_size() -> u32 {
+    env::var("DATABASE_POOL_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(10)
+}
+
+pub fn pool() -> &'static DbPool {
+    &POOL
+}
+
+///Checks a connection out of the pool, failing when none frees up in time.
+pub fn try_connection() -> Result<DbConnection, PoolError> {
+    connection_from(&POOL)
+}
+
+///Like [`try_connection`], for a pool handed to a component instead of the
+///global one.
+pub fn connection_from(pool: &DbPool) -> Result<DbConnection, PoolError> {
+    let started = Instant::now();
+    let conn = pool.get();
+    metrics::observe_pool_wait(started);
+    conn
+}
+
+///Like [`connection_from`], but reports a failed checkout as a
+///`ClosedConnection` error, so callers can tell an unreachable database apart
+///from a failed query.
+pub fn connect(pool: &DbPool) -> QueryResult<DbConnection> {
+    connection_from(pool).map_err(|e| {
+        Error::DatabaseError(DatabaseErrorKind::ClosedConnection, Box::new(e.to_string()))
+    })
+}
+
+///The pool the app was built with, see [`app::AppConfig`].
+pub fn app_pool(req: &HttpRequest) -> Option<DbPool> {
+    req.app_data::<web::Data<DbPool>>()
+        .map(|pool| pool.get_ref().clone())
+}
+
+pub fn establish_connection() -> DbConnection {
+    try_connection().unwrap_or_else(|e| panic!("Error connecting to the database: {e}"))
+}
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+///Public base URL used in links sent by email, e.g. `https://example.com`.
+///Defaults to the local server when APP_URL isn't set.
+pub fn app_url() -> String {
+    env::var("APP_URL").unwrap_or_else(|_| {
+        let port = env::var("PORT").unwrap_or_else(|_| String::from("3000"));
+        format!("http://127.0.0.1:{port}")
+    })
 }
 
 //pub fn render(file: &str, context: Context) -> Result<HttpResponse, actix_web::Error> {
@@ -37,46 +134,67 @@ pub fn establish_connection() -> PgConnection {
 //}
 //}
 
-pub fn render(file: &str, context: Context) -> HttpResponse {
-    let template = TEMPLATES.render(file, &context).unwrap();
-    HttpResponse::Ok().body(template)
-}
-
-pub async fn register(user: UserRegistration) -> Result<(), ValidationError> {
-    let UserRegistration {
-        first_name,
-        last_name,
-        email,
-        _password,
-        _confirm_password,
-    } = user;
-    let hashed_password = password_hasher(&_password.unwrap()).await;
-    let new_user = NewUser {
-        first_name: first_name.unwrap(),
-        last_name: last_name.unwrap(),
-        email: email.unwrap(),
-        password: hashed_password.unwrap(),
+///Renders a template. Pages that don't set `lang` are rendered in English.
+pub fn render(file: &str, mut context: Context) -> HttpResponse {
+    if !context.contains_key("lang") {
+        context.insert("lang", &i18n::Locale::default());
     };
-    create_user(new_user).await
+    let template = TEMPLATES.render(file, &context).unwrap();
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(template)
 }
 
-async fn password_hasher(password_str: &str) -> Result<String, argon2::password_hash::Error> {
-    let password = password_str.as_bytes();
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let password_hash = argon2.hash_password(password, &salt)?.to_string();
-    Ok(password_hash)
+///Whether the templates could be loaded, which every page depends on.
+pub fn templates_loaded() -> bool {
+    std::panic::catch_unwind(|| TEMPLATES.get_template_names().next().is_some()).unwrap_or(false)
 }
 
-async fn create_user(new_user: NewUser) -> Result<(), ValidationError> {
-    use schema::users::dsl::*;
-    let conn = &mut establish_connection();
-    if insert_into(users).values(new_user).execute(conn).is_err() {
-        let mut registration_error = ValidationError::new("registration_error");
-        registration_error.message = Some(Cow::Borrowed("An error occured during registration"));
-        return Err(registration_error);
+///Loads the user the session identity belongs to. Anonymous requests and
+///identities that don't hold a valid user id (e.g. sessions created before ids
+///were stored) yield `None`; an unreachable database is a 503.
+pub async fn current_user(
+    pool: &DbPool,
+    identity: Option<Identity>,
+) -> Result<Option<User>, ApiError> {
+    let user_id = identity.and_then(|identity| identity.id().ok()?.parse::<i32>().ok());
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None => return Ok(None),
     };
-    Ok(())
+    let pool = pool.clone();
+    match web::block(move || User::find(&pool, user_id).optional()).await {
+        Ok(user) => Ok(user?),
+        Err(e) => {
+            log::error!("Error loading user: {e}");
+            Err(ApiError::internal())
+        }
+    }
+}
+
+///Renders a short message page, e.g. the outcome of a form that has no page of
+///its own.
+pub fn render_message(status: StatusCode, title: &str, message: &str, back: &str) -> HttpResponse {
+    render_message_in(i18n::Locale::default(), status, title, message, back)
+}
+
+///Like [`render_message`], for a page in the locale. The title and message
+///must already be in its language.
+pub fn render_message_in(
+    locale: i18n::Locale,
+    status: StatusCode,
+    title: &str,
+    message: &str,
+    back: &str,
+) -> HttpResponse {
+    let mut context = Context::new();
+    context.insert("lang", &locale);
+    context.insert("title", title);
+    context.insert("message", message);
+    context.insert("back", back);
+    let mut response = render("message.html", context);
+    *response.status_mut() = status;
+    response
 }
 
 pub fn response(
@@ -95,15 +213,3 @@ pub fn response(
         .content_type(content_type)
         .body(body.unwrap())
 }
-
-pub async fn not_allowed() -> impl Responder {
-    HttpResponse::build(StatusCode::METHOD_NOT_ALLOWED)
-        .content_type("text/html; charset=utf-8")
-        .body("<h1>405 Not Allowed</h1>")
-}
-
-pub async fn not_found() -> impl Responder {
-    HttpResponse::build(StatusCode::NOT_FOUND)
-        .content_type("text/html; charset=utf-8")
-        .body("<h1>404</h1><p>Page Not Found</p>")
-}
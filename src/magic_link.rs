@@ -0,0 +1,80 @@
+use crate::models::NewLoginLink;
+use crate::tokens::{hash_token, random_token};
+use crate::{connect, DbPool};
+use chrono::Utc;
+use diesel::{delete, insert_into, prelude::*, update};
+
+const LINK_LIFETIME_MINUTES: i64 = 15;
+
+///Creates a single-use login link token for the user. Only its hash is stored.
+pub fn create(pool: &DbPool, user_id: i32) -> QueryResult<String> {
+    use crate::schema::login_links::dsl::login_links;
+    let token = random_token(32);
+    let new_link = NewLoginLink {
+        user_id,
+        token_hash: hash_token(&token),
+        expires_at: (Utc::now() + chrono::Duration::minutes(LINK_LIFETIME_MINUTES)).naive_utc(),
+    };
+    let conn = &mut connect(pool)?;
+    insert_into(login_links).values(new_link).execute(conn)?;
+    Ok(token)
+}
+
+///Marks the link as used and returns its user id, if it is unused and unexpired.
+///The check and the update happen in one statement so a link can't be consumed twice.
+pub fn consume(pool: &DbPool, token: &str) -> QueryResult<Option<i32>> {
+    use crate::schema::login_links::dsl::*;
+    let now = Utc::now().naive_utc();
+    let conn = &mut connect(pool)?;
+    update(
+        login_links
+            .filter(token_hash.eq(hash_token(token)))
+            .filter(used_at.is_null())
+            .filter(expires_at.gt(now)),
+    )
+    .set(used_at.eq(now))
+    .returning(user_id)
+    .get_result::<i32>(conn)
+    .optional()
+}
+
+///Deletes used and expired links, returning how many there were.
+pub fn purge(pool: &DbPool) -> QueryResult<usize> {
+    use crate::schema::login_links::dsl::*;
+    let conn = &mut connect(pool)?;
+    delete(
+        login_links.filter(
+            used_at
+                .is_not_null()
+                .or(expires_at.le(Utc::now().naive_utc())),
+        ),
+    )
+    .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_link_is_single_use() {
+        let pool = testing::pool();
+        let user = testing::create_user("user");
+        let token = create(&pool, user.id).unwrap();
+        assert_eq!(consume(&pool, &token), Ok(Some(user.id)));
+        assert_eq!(consume(&pool, &token), Ok(None));
+        assert_eq!(consume(&pool, "not-a-token"), Ok(None));
+    }
+
+    #[test]
+    fn test_purge_keeps_unused_links() {
+        let pool = testing::pool();
+        let user = testing::create_user("user");
+        let used = create(&pool, user.id).unwrap();
+        let unused = create(&pool, user.id).unwrap();
+        consume(&pool, &used).unwrap();
+        assert!(purge(&pool).unwrap() >= 1);
+        assert_eq!(consume(&pool, &unused), Ok(Some(user.id)));
+    }
+}
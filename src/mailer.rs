@@ -0,0 +1,104 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+#[derive(Clone, Debug)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    fn to_rfc822(&self) -> String {
+        format!(
+            "To: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            self.to, self.subject, self.body
+        )
+    }
+}
+
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> io::Result<()>;
+}
+
+///Writes every email as an `.eml` file into a directory, handy for local
+///development where no SMTP server is available.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> FileMailer {
+        FileMailer { dir: dir.into() }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let file_name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            Uuid::new_v4()
+        );
+        fs::write(self.dir.join(file_name), email.to_rfc822())
+    }
+}
+
+///Keeps sent emails in memory so tests can inspect them.
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl MemoryMailer {
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl Mailer for MemoryMailer {
+    fn send(&self, email: &Email) -> io::Result<()> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+///Builds the mailer selected by the MAILER env variable ("file" or "memory").
+///The file mailer writes into MAIL_DIR, which defaults to `./mail`.
+pub fn from_env() -> Arc<dyn Mailer> {
+    match env::var("MAILER").as_deref() {
+        Ok("memory") => Arc::new(MemoryMailer::default()),
+        _ => {
+            let dir = env::var("MAIL_DIR").unwrap_or_else(|_| String::from("mail"));
+            Arc::new(FileMailer::new(dir))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_mailer_writes_eml() {
+        let dir = env::temp_dir().join(format!("web_app-mail-{}", Uuid::new_v4()));
+        let mailer = FileMailer::new(&dir);
+        let email = Email {
+            to: String::from("frodo@theshire.com"),
+            subject: String::from("Hello"),
+            body: String::from("Second breakfast?"),
+        };
+        mailer.send(&email).unwrap();
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let contents = fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(contents.contains("To: frodo@theshire.com"));
+        assert!(contents.ends_with("Second breakfast?\r\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,39 +1,188 @@
-use actix_files as fs;
-use actix_identity::IdentityMiddleware;
-use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, middleware::Logger, web, App, HttpServer};
+use actix_web::HttpServer;
+use clap::Parser;
 use dotenvy::dotenv;
-use std::env;
-use web_app::not_found;
-use web_app::{routes::home, routes::index};
+use ipnet::IpNet;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, process};
+use web_app::app::{build_app, build_redirect_app, AppConfig};
+use web_app::proxy::{self, TrustedProxies};
+use web_app::tls::{self, CertResolver};
+use web_app::{migrations, shutdown, telemetry, workers};
 
-///Be sure to set DATABASE_URL, PORT, and RUST_LOG .env variables to run the binary
+///Be sure to set DATABASE_URL, SESSION_KEY, PORT, and RUST_LOG .env variables to run the binary
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    ///Run the pending database migrations and exit
+    #[arg(long)]
+    migrate: bool,
+    ///Run the pending database migrations before starting the server
+    #[arg(long, env = "MIGRATE_ON_STARTUP")]
+    migrate_on_startup: bool,
+    ///Seconds requests in flight get to finish on SIGTERM or SIGINT
+    #[arg(long, env = "SHUTDOWN_TIMEOUT", default_value_t = 30)]
+    shutdown_timeout: u64,
+    ///Seconds to report unready on /readyz before draining, so load balancers
+    ///stop routing requests here first
+    #[arg(long, env = "SHUTDOWN_DELAY", default_value_t = 0)]
+    shutdown_delay: u64,
+    ///Addresses to listen on, comma separated, e.g. `0.0.0.0:443,[::1]:443`.
+    ///`[::]` usually accepts IPv4 connections too. Defaults to 127.0.0.1 on PORT
+    #[arg(long, env = "BIND", value_delimiter = ',')]
+    bind: Vec<SocketAddr>,
+    ///PEM certificate chain. Serves HTTPS instead of HTTP on every bind
+    ///address; send SIGHUP to reload it along with the key
+    #[arg(long, env = "TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    ///PEM private key of the certificate
+    #[arg(long, env = "TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    ///Plain HTTP addresses redirecting to HTTPS, comma separated, e.g.
+    ///`0.0.0.0:80`
+    #[arg(
+        long,
+        env = "REDIRECT_BIND",
+        value_delimiter = ',',
+        requires = "tls_cert"
+    )]
+    redirect_bind: Vec<SocketAddr>,
+    ///Reverse proxies whose Forwarded and X-Forwarded-* headers are believed,
+    ///as comma separated addresses or CIDRs, e.g. `10.0.0.0/8,fd00::/8`
+    #[arg(long, env = "TRUSTED_PROXIES", value_delimiter = ',', value_parser = proxy::parse_network)]
+    trusted_proxies: Vec<IpNet>,
+}
+
+///Brings the schema up to date when asked to, and otherwise makes sure it
+///already is. The server never runs against a stale schema.
+fn check_schema(args: &Args) -> Result<(), String> {
+    if args.migrate || args.migrate_on_startup {
+        let applied = migrations::run().map_err(|e| format!("Error running migrations: {e}"))?;
+        for version in &applied {
+            tracing::info!(version, "applied migration");
+        }
+        if applied.is_empty() {
+            tracing::info!("no pending migrations");
+        };
+        return Ok(());
+    };
+    let pending = migrations::pending()
+        .map_err(|e| format!("Refusing to start: unable to check for pending migrations: {e}"))?;
+    if !pending.is_empty() {
+        return Err(format!(
+            "Refusing to start: {} pending migration(s): {}. Run with --migrate, or --migrate-on-startup (MIGRATE_ON_STARTUP=true).",
+            pending.len(),
+            pending.join(", ")
+        ));
+    };
+    Ok(())
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    //load .env first, flags can be set from the environment
     dotenv().ok();
-    env_logger::init();
+    let args = Args::parse();
+    telemetry::init();
+
+    if let Err(e) = check_schema(&args) {
+        tracing::error!("{e}");
+        process::exit(1);
+    };
+    if args.migrate {
+        return Ok(());
+    };
+
+    let mut binds = args.bind;
+    if binds.is_empty() {
+        let port: u16 = env::var("PORT")
+            .unwrap_or_else(|_| String::from("3000"))
+            .parse()
+            .expect("Error parsing PORT variable: ");
+        binds.push(SocketAddr::from(([127, 0, 0, 1], port)));
+    };
+    let resolver = match (args.tls_cert, args.tls_key) {
+        (Some(cert), Some(key)) => match CertResolver::new(cert, key) {
+            Ok(resolver) => Some(Arc::new(resolver)),
+            Err(e) => {
+                tracing::error!("Error loading the TLS certificate: {e}");
+                process::exit(1);
+            }
+        },
+        _ => None,
+    };
 
-    let port: u16 = env::var("PORT")
-        .unwrap_or_else(|_| String::from("3000"))
-        .parse()
-        .expect("Error parsing PORT variable: ");
+    let config = match AppConfig::from_env() {
+        Ok(config) => AppConfig {
+            secure_cookies: resolver.is_some(),
+            trusted_proxies: TrustedProxies::new(args.trusted_proxies),
+            ..config
+        },
+        Err(e) => {
+            tracing::error!("Refusing to start: {e}");
+            process::exit(1);
+        }
+    };
+    let shutdown = config.shutdown.clone();
+    let pool = config.pool.clone();
+    let mut workers = workers::spawn(&shutdown, &pool);
+    let mut server = HttpServer::new(move || build_app(config.clone()))
+        //signals are handled by `shutdown::serve`, which flips readiness first
+        .disable_signals()
+        .shutdown_timeout(args.shutdown_timeout);
+    for address in &binds {
+        server = match &resolver {
+            Some(resolver) => server.bind_rustls(address, tls::server_config(resolver.clone()))?,
+            None => server.bind(address)?,
+        };
+        tracing::info!(%address, tls = resolver.is_some(), "listening");
+    }
+    let server = server.run();
+    if let Some(resolver) = resolver {
+        workers.push(actix_web::rt::spawn(tls::reload_on_sighup(
+            resolver,
+            shutdown.clone(),
+        )));
+    };
 
-    HttpServer::new(|| {
-        App::new()
-            .wrap(IdentityMiddleware::default())
-            .wrap(Logger::default())
-            .wrap(
-                SessionMiddleware::builder(CookieSessionStore::default(), Key::from(&[0; 64]))
-                    .cookie_secure(false)
-                    .build(),
-            )
-            .configure(index)
-            .configure(home::index)
-            .service(fs::Files::new("/static", "./static"))
-            .default_service(web::to(not_found))
-    })
-    .bind(("127.0.0.1", port))?
-    .run()
-    .await
+    let delay = Duration::from_secs(args.shutdown_delay);
+    let redirects = if args.redirect_bind.is_empty() {
+        None
+    } else {
+        let https_port = binds[0].port();
+        let redirect_shutdown = shutdown.clone();
+        let mut redirect_server = HttpServer::new(move || {
+            build_redirect_app(pool.clone(), redirect_shutdown.clone(), https_port)
+        })
+        .disable_signals()
+        .shutdown_timeout(args.shutdown_timeout);
+        for address in &args.redirect_bind {
+            redirect_server = redirect_server.bind(address)?;
+            tracing::info!(%address, "redirecting to HTTPS");
+        }
+        //stops along with the main server
+        let started = shutdown.clone();
+        let stop = async move { started.wait().await };
+        Some(actix_web::rt::spawn(shutdown::serve(
+            redirect_server.run(),
+            shutdown.clone(),
+            stop,
+            delay,
+        )))
+    };
+    let stop = async {
+        let signal = shutdown::signal().await;
+        tracing::info!(signal, "shutting down");
+    };
+    shutdown::serve(server, shutdown, stop, delay).await?;
+    if let Some(redirects) = redirects {
+        redirects.await??;
+    };
+    for worker in workers {
+        worker.await.ok();
+    }
+    tracing::info!("stopped");
+    Ok(())
 }
@@ -0,0 +1,169 @@
+//! Prometheus metrics, exported at `/metrics`. Requests are counted and timed
+//! by [`track_requests`] under their route pattern (e.g. `/api/v1/users/{id}`)
+//! rather than their path, so ids don't multiply the series.
+use crate::audit::{AuthEventKind, AuthOutcome};
+use crate::DbPool;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    Error,
+};
+use actix_web_lab::middleware::Next;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+use std::time::Instant;
+
+///Route label of requests that didn't match a route, e.g. 404s.
+const UNMATCHED: &str = "unmatched";
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests by method, route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latency by method and route",
+        &["method", "route"]
+    )
+    .unwrap();
+    static ref LOGINS: IntCounterVec =
+        register_int_counter_vec!("logins_total", "Login attempts by outcome", &["outcome"])
+            .unwrap();
+    static ref REGISTRATIONS: IntCounter =
+        register_int_counter!("registrations_total", "Accounts registered").unwrap();
+    static ref PASSWORD_HASH_DURATION: HistogramVec = register_histogram_vec!(
+        "password_hash_duration_seconds",
+        "Time spent hashing and verifying passwords with Argon2",
+        &["operation"],
+        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]
+    )
+    .unwrap();
+    static ref DB_POOL_CONNECTIONS: IntGauge = register_int_gauge!(
+        "db_pool_connections",
+        "Connections open in the database pool"
+    )
+    .unwrap();
+    static ref DB_POOL_IDLE_CONNECTIONS: IntGauge = register_int_gauge!(
+        "db_pool_idle_connections",
+        "Open connections in the database pool that aren't checked out"
+    )
+    .unwrap();
+    static ref DB_POOL_MAX_CONNECTIONS: IntGauge =
+        register_int_gauge!("db_pool_max_connections", "Size limit of the database pool").unwrap();
+    static ref DB_POOL_WAIT: Histogram = register_histogram!(
+        "db_pool_wait_seconds",
+        "Time spent waiting to check a connection out of the pool",
+        vec![0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]
+    )
+    .unwrap();
+}
+
+///Counts and times every request, labelled with the route it matched.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let response = next.call(req).await;
+    let elapsed = started.elapsed().as_secs_f64();
+    let (route, status) = match &response {
+        Ok(response) => (
+            response.request().match_pattern(),
+            response.status().as_u16(),
+        ),
+        //errors that never became a response are answered with a 500
+        Err(_) => (None, 500),
+    };
+    let route = route.unwrap_or_else(|| String::from(UNMATCHED));
+    HTTP_REQUESTS
+        .with_label_values(&[&method, &route, &status.to_string()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(elapsed);
+    response
+}
+
+///Counts logins and registrations as they're written to the audit log.
+pub fn record_auth_event(event: AuthEventKind, outcome: AuthOutcome) {
+    match (event, outcome) {
+        (AuthEventKind::Login, outcome) => LOGINS.with_label_values(&[outcome.as_str()]).inc(),
+        (AuthEventKind::Register, AuthOutcome::Success) => REGISTRATIONS.inc(),
+        _ => (),
+    };
+}
+
+///Runs an Argon2 `operation` ("hash" or "verify"), recording how long it took.
+pub fn time_password_hash<T>(operation: &str, f: impl FnOnce() -> T) -> T {
+    let timer = PASSWORD_HASH_DURATION
+        .with_label_values(&[operation])
+        .start_timer();
+    let result = f();
+    timer.observe_duration();
+    result
+}
+
+///Records how long checking out a database connection took.
+pub fn observe_pool_wait(started: Instant) {
+    DB_POOL_WAIT.observe(started.elapsed().as_secs_f64());
+}
+
+///Every metric in the Prometheus text format, with the gauges of `pool`.
+pub fn render(pool: &DbPool) -> String {
+    let state = pool.state();
+    DB_POOL_CONNECTIONS.set(state.connections.into());
+    DB_POOL_IDLE_CONNECTIONS.set(state.idle_connections.into());
+    DB_POOL_MAX_CONNECTIONS.set(pool.max_size().into());
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::Endpoint;
+    use actix_web::{test, App, HttpResponse};
+    use actix_web_lab::middleware::from_fn;
+
+    #[actix_web::test]
+    async fn test_requests_are_labelled_by_route() {
+        let app = test::init_service(App::new().wrap(from_fn(track_requests)).service(
+            Endpoint::new("/metrics-test/{id}").get(|| async { HttpResponse::Ok().finish() }),
+        ))
+        .await;
+        for uri in [
+            "/metrics-test/1",
+            "/metrics-test/2",
+            "/metrics-test-missing",
+        ] {
+            let request = test::TestRequest::get().uri(uri).to_request();
+            test::call_service(&app, request).await;
+        }
+        let labels = |route: &str, status: &str| {
+            HTTP_REQUESTS
+                .with_label_values(&["GET", route, status])
+                .get()
+        };
+        assert_eq!(labels("/metrics-test/{id}", "200"), 2);
+        assert!(labels(UNMATCHED, "404") >= 1);
+
+        time_password_hash("verify", || ());
+        let exported = render(&crate::testing::pool());
+        assert!(exported.contains(
+            r#"http_requests_total{method="GET",route="/metrics-test/{id}",status="200"} 2"#
+        ));
+        assert!(exported.contains("password_hash_duration_seconds_count{operation=\"verify\"}"));
+        assert!(exported.contains("db_pool_max_connections"));
+    }
+}
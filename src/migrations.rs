@@ -0,0 +1,37 @@
+//! The schema migrations in `migrations/`, embedded in the binary so deploys
+//! don't need the diesel CLI.
+use crate::try_connection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use std::error::Error;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+
+pub type MigrationResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+///Names of the migrations the database hasn't run yet.
+pub fn pending() -> MigrationResult<Vec<String>> {
+    let conn = &mut try_connection()?;
+    let pending = conn.pending_migrations(MIGRATIONS)?;
+    Ok(pending
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect())
+}
+
+///Runs the pending migrations, returning the versions that ran.
+pub fn run() -> MigrationResult<Vec<String>> {
+    let conn = &mut try_connection()?;
+    let applied = conn.run_pending_migrations(MIGRATIONS)?;
+    Ok(applied.iter().map(ToString::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_is_current() {
+        assert_eq!(pending().unwrap(), Vec::<String>::new());
+        assert!(run().unwrap().is_empty());
+    }
+}
@@ -1,5 +1,9 @@
-use crate::establish_connection;
-use crate::schema::users;
+use crate::schema::{
+    api_tokens, auth_events, login_links, remember_tokens, user_identities, users,
+};
+use crate::services::users::ROLES;
+use crate::validation;
+use crate::{connect, DbPool};
 use argon2::{
     password_hash::{PasswordHash, PasswordVerifier},
     Argon2,
@@ -7,26 +11,115 @@ use argon2::{
 use diesel::prelude::*;
 use lazy_static::lazy_static;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::openapi::{KnownFormat, ObjectBuilder, RefOr, Schema, SchemaFormat, SchemaType};
+use utoipa::ToSchema;
 use validator::{Validate, ValidationError};
 
+pub const PASSWORD_MIN_LENGTH: u64 = 8;
+//a password needs one character of each class. They are spelled out rather
+//than `\d` and `\W`, which are Unicode-aware in Rust but ASCII-only in the
+//JavaScript that checks the form's `pattern`
+const UPPER_CASE_CHAR: &str = "[A-Z]";
+const LOWER_CASE_CHAR: &str = "[a-z]";
+const NUMBER: &str = "[0-9]";
+const NON_ALPHA_CHAR: &str = "[^A-Za-z0-9_]";
+
 lazy_static! {
-    static ref ONE_UPPER_CASE_CHAR: Regex = Regex::new(r"[A-Z]+").unwrap();
-    static ref ONE_LOWER_CASE_CHAR: Regex = Regex::new(r"[a-z]+").unwrap();
-    static ref ONE_NUMBER: Regex = Regex::new(r"\d+").unwrap();
-    static ref ONE_NON_ALPHA_CHAR: Regex = Regex::new(r"\W+").unwrap();
+    static ref ONE_UPPER_CASE_CHAR: Regex = Regex::new(UPPER_CASE_CHAR).unwrap();
+    static ref ONE_LOWER_CASE_CHAR: Regex = Regex::new(LOWER_CASE_CHAR).unwrap();
+    static ref ONE_NUMBER: Regex = Regex::new(NUMBER).unwrap();
+    static ref ONE_NON_ALPHA_CHAR: Regex = Regex::new(NON_ALPHA_CHAR).unwrap();
     static ref NO_SPACES: Regex = Regex::new(r"^[^ ]+$").unwrap();
+    ///The password rules as one HTML `pattern`, which unlike the `regex` crate
+    ///supports lookaheads.
+    pub static ref PASSWORD_PATTERN: String = format!(
+        "^(?=.*{UPPER_CASE_CHAR})(?=.*{LOWER_CASE_CHAR})(?=.*{NUMBER})(?=.*{NON_ALPHA_CHAR})[^ ]+$"
+    );
 }
 
-#[derive(Queryable)]
+///Checks the password rules shared by every form that sets a password. Only
+///the first rule the password breaks is reported.
+fn validate_password(password: &str) -> Result<(), ValidationError> {
+    let (code, message) = if (password.chars().count() as u64) < PASSWORD_MIN_LENGTH {
+        ("password_length", "Password must be at least 8 characters")
+    } else if !NO_SPACES.is_match(password) {
+        ("password_spaces", "Password must not contain spaces")
+    } else if !ONE_UPPER_CASE_CHAR.is_match(password) {
+        (
+            "password_uppercase",
+            "Password must contain at least one uppercase character",
+        )
+    } else if !ONE_LOWER_CASE_CHAR.is_match(password) {
+        (
+            "password_lowercase",
+            "Password must contain at least one lowercase character",
+        )
+    } else if !ONE_NUMBER.is_match(password) {
+        (
+            "password_number",
+            "Password must contain at least one number",
+        )
+    } else if !ONE_NON_ALPHA_CHAR.is_match(password) {
+        (
+            "password_special",
+            "Password must contain at least one special character",
+        )
+    } else {
+        return Ok(());
+    };
+    let mut error = validation::error(code, message);
+    error.add_param(std::borrow::Cow::Borrowed("min"), &PASSWORD_MIN_LENGTH);
+    Err(error)
+}
+
+///Schema of password fields, built from the constants their validation uses so
+///the forms derived from it check the same rules.
+pub struct PasswordSchema;
+
+impl<'s> ToSchema<'s> for PasswordSchema {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let schema = ObjectBuilder::new()
+            .schema_type(SchemaType::String)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::Password)))
+            .min_length(Some(PASSWORD_MIN_LENGTH as usize))
+            .pattern(Some(PASSWORD_PATTERN.as_str()))
+            .build();
+        ("Password", schema.into())
+    }
+}
+
+#[derive(Clone, Debug, Queryable, Serialize, ToSchema)]
 pub struct User {
     pub id: i32,
     pub first_name: String,
     pub last_name: String,
+    #[schema(format = "email")]
     pub email: String,
+    #[serde(skip_serializing)]
     pub password: String,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    #[schema(example = "user")]
+    pub role: String,
+}
+
+impl User {
+    pub fn find(pool: &DbPool, user_id: i32) -> QueryResult<User> {
+        use crate::schema::users::dsl::*;
+        let conn = &mut connect(pool)?;
+        users.find(user_id).first(conn)
+    }
+
+    pub fn find_by_email(pool: &DbPool, value: &str) -> QueryResult<User> {
+        use crate::schema::users::dsl::*;
+        let conn = &mut connect(pool)?;
+        users.filter(email.eq(value)).first(conn)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == "admin"
+    }
 }
 
 #[derive(Insertable, Deserialize)]
@@ -38,130 +131,226 @@ pub struct NewUser {
     pub password: String,
 }
 
+#[derive(Queryable, Serialize)]
+pub struct AuthEvent {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub email: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub event: String,
+    pub outcome: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=auth_events)]
+pub struct NewAuthEvent {
+    pub user_id: Option<i32>,
+    pub email: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub event: String,
+    pub outcome: String,
+}
+
+#[derive(Queryable)]
+pub struct RememberToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub selector: String,
+    pub validator_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=remember_tokens)]
+pub struct NewRememberToken {
+    pub user_id: i32,
+    pub selector: String,
+    pub validator_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=login_links)]
+pub struct NewLoginLink {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=user_identities)]
+pub struct NewUserIdentity {
+    pub user_id: i32,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub scopes: String,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=api_tokens)]
+pub struct NewApiToken {
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: String,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
 #[derive(Debug, Validate, Deserialize)]
-#[validate(schema(
-    function = "custom_login_validator",
-    message = "Invalid Credentials",
-    skip_on_field_errors = true
-))]
+pub struct ApiTokenRequest {
+    #[validate(length(min = 1, max = 100, message = "Name must be 1 to 100 characters"))]
+    pub name: String,
+    pub scope_read: Option<String>,
+    pub scope_write: Option<String>,
+    pub expires_in_days: Option<String>,
+}
+
+#[derive(Debug, Validate, Deserialize, ToSchema)]
+pub struct UserUpdate {
+    #[validate(required, custom = "custom_role_validator")]
+    #[schema(required = true, nullable = false, example = "admin")]
+    pub role: Option<String>,
+}
+
+fn custom_role_validator(role: &str) -> Result<(), ValidationError> {
+    if ROLES.contains(&role) {
+        return Ok(());
+    };
+    let mut error = ValidationError::new("role");
+    error.message = Some(std::borrow::Cow::Owned(format!(
+        "Role must be one of: {}",
+        ROLES.join(", ")
+    )));
+    Err(error)
+}
+
+#[derive(Debug, Validate, Serialize, Deserialize, ToSchema)]
+pub struct MagicLinkRequest {
+    #[validate(email, required, length(min = 1, code = "blank", message = "Required"))]
+    #[schema(required = true, nullable = false, format = "email", min_length = 1)]
+    pub email: Option<String>,
+}
+
+///Credentials are checked by `AuthService::authenticate`; the attributes here
+///only cover the shape of the input.
+#[derive(Debug, Validate, Serialize, Deserialize, ToSchema)]
 pub struct UserLogin {
-    #[validate(email, required, length(min = 1, message = "Required"))]
+    #[validate(email, required, length(min = 1, code = "blank", message = "Required"))]
+    #[schema(required = true, nullable = false, format = "email", min_length = 1)]
     pub email: Option<String>,
-    #[validate(required, length(min = 1, message = "Required"))]
+    #[validate(required, length(min = 1, code = "blank", message = "Required"))]
+    #[schema(required = true, nullable = false, format = Password, min_length = 1)]
     pub password: Option<String>,
+    ///Keep the user logged in across browser sessions.
+    #[serde(default, deserialize_with = "checkbox")]
+    #[schema(default = false)]
+    pub remember: bool,
+}
+
+///Accepts both JSON booleans and the "on" value browsers submit for a checked
+///checkbox.
+fn checkbox<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Checkbox {
+        Bool(bool),
+        Str(String),
+    }
+    Ok(match Checkbox::deserialize(deserializer)? {
+        Checkbox::Bool(value) => value,
+        Checkbox::Str(value) => matches!(value.as_str(), "on" | "true" | "1"),
+    })
 }
 
-#[derive(Serialize, Deserialize, Validate, Debug)]
+///Email uniqueness is checked by `AuthService::create_account`; the attributes
+///here only cover the shape of the input.
+#[derive(Serialize, Deserialize, Validate, Debug, ToSchema)]
 pub struct UserRegistration {
-    #[validate(required, length(min = 1, message = "Required"))]
+    #[validate(required, length(min = 1, code = "blank", message = "Required"))]
+    #[schema(required = true, nullable = false, min_length = 1)]
     pub first_name: Option<String>,
-    #[validate(required, length(min = 1, message = "Required"))]
+    #[validate(required, length(min = 1, code = "blank", message = "Required"))]
+    #[schema(required = true, nullable = false, min_length = 1)]
     pub last_name: Option<String>,
-    #[validate(
-        custom(function = "custom_registration_email_validator",),
-        email,
-        required,
-        length(min = 1, message = "Required")
-    )]
+    #[validate(email, required, length(min = 1, code = "blank", message = "Required"))]
+    #[schema(required = true, nullable = false, format = "email", min_length = 1)]
     pub email: Option<String>,
     #[validate(
-        regex(
-            path = "ONE_UPPER_CASE_CHAR",
-            message = "Password must contain at least one uppercase character"
-        ),
-        regex(
-            path = "ONE_LOWER_CASE_CHAR",
-            message = "Password must contain at least one lowercase character"
-        ),
-        regex(
-            path = "ONE_NUMBER",
-            message = "Password must contain at least one number"
-        ),
-        regex(
-            path = "ONE_NON_ALPHA_CHAR",
-            message = "Password must contain at least one special character"
-        ),
-        regex(path = "NO_SPACES", message = "Password must not contain spaces"),
-        must_match(other = "_confirm_password", message = "Passwords must match"),
-        length(min = 8, message = "Password must be at least 8 characters"),
         required,
-        length(min = 1, message = "Required")
+        length(min = 1, code = "blank", message = "Required"),
+        custom = "validate_password",
+        must_match(
+            other = "_confirm_password",
+            code = "password_mismatch",
+            message = "Passwords must match"
+        )
     )]
     #[serde(rename = "password")]
+    #[schema(required = true, value_type = PasswordSchema, inline)]
     pub _password: Option<String>,
     #[validate(
-        length(min = 8, message = "Password must be at least 8 characters"),
-        must_match(other = "_password", message = "Passwords must match"),
-        regex(
-            path = "ONE_UPPER_CASE_CHAR",
-            message = "Password must contain at least one uppercase character"
-        ),
-        regex(
-            path = "ONE_LOWER_CASE_CHAR",
-            message = "Password must contain at least one lowercase character"
-        ),
-        regex(
-            path = "ONE_NUMBER",
-            message = "Password must contain at least one number"
-        ),
-        regex(
-            path = "ONE_NON_ALPHA_CHAR",
-            message = "Password must contain at least one special character"
-        ),
-        regex(path = "NO_SPACES", message = "Password must not contain spaces"),
         required,
-        length(min = 1, message = "Required")
+        length(min = 1, code = "blank", message = "Required"),
+        custom = "validate_password",
+        must_match(
+            other = "_password",
+            code = "password_mismatch",
+            message = "Passwords must match"
+        )
     )]
     #[serde(rename = "confirm_password")]
+    #[schema(required = true, value_type = PasswordSchema, inline)]
     pub _confirm_password: Option<String>,
 }
 
-fn custom_registration_email_validator(value: &str) -> Result<(), ValidationError> {
-    email_count(value, 0)
-}
-
-fn custom_login_validator(user_login: &UserLogin) -> Result<(), ValidationError> {
-    let UserLogin { email, password } = user_login;
-    let email = email.as_ref().unwrap();
-    let password = password.as_ref().unwrap();
-    if email_count(email, 1).is_err() || custom_login_password_validator(password, email).is_err() {
-        return Err(ValidationError::new("invalid"));
-    };
-    Ok(())
-}
-
-fn custom_login_password_validator(value: &str, arg: &str) -> Result<(), ValidationError> {
-    use crate::schema::users::dsl::*;
-    let mut conn = establish_connection();
-    let db_password = users
-        .select(password)
-        .filter(email.eq(arg))
-        .first::<String>(&mut conn);
-    if db_password.is_err() {
-        return Err(ValidationError::new("invalid"));
-    };
-    let password_check = password_hash_checker(value, &db_password.unwrap());
-    if password_check.is_err() {
-        return Err(ValidationError::new("invalid"));
-    };
-    Ok(())
-}
-
-fn email_count(value: &str, count: usize) -> Result<(), ValidationError> {
-    use super::schema::users::dsl::*;
-    let mut conn = establish_connection();
-    let email_unique = users
-        .select(email)
-        .filter(email.eq(value))
-        .limit(2)
-        .load::<String>(&mut conn);
-    if email_unique.is_err() || email_unique.unwrap().len() != count {
-        return Err(ValidationError::new("email"));
-    };
-    Ok(())
+///A new password for an existing account, checked by the same rules as
+///[`UserRegistration`].
+#[derive(Deserialize, Validate, Debug)]
+pub struct PasswordReset {
+    #[validate(
+        required,
+        length(min = 1, code = "blank", message = "Required"),
+        custom = "validate_password",
+        must_match(
+            other = "_confirm_password",
+            code = "password_mismatch",
+            message = "Passwords must match"
+        )
+    )]
+    #[serde(rename = "password")]
+    pub _password: Option<String>,
+    #[validate(
+        required,
+        length(min = 1, code = "blank", message = "Required"),
+        custom = "validate_password",
+        must_match(
+            other = "_password",
+            code = "password_mismatch",
+            message = "Passwords must match"
+        )
+    )]
+    #[serde(rename = "confirm_password")]
+    pub _confirm_password: Option<String>,
 }
 
-fn password_hash_checker(
+pub(crate) fn password_hash_checker(
     password: &str,
     password_hash: &str,
 ) -> Result<(), argon2::password_hash::Error> {
@@ -179,4 +368,32 @@ mod tests {
         let password_hash = "$argon2id$v=19$m=4096,t=3,p=1$A2uYmfHJZkAQ55CCvpTujA$aBoQLUaRrqIQl33JcKRqy+x7a/WQBpNEsuJJjCUylyk";
         assert_eq!(password_hash_checker(password, password_hash).unwrap(), ());
     }
+
+    #[actix_web::test]
+    async fn test_password_rules_match_the_form_pattern() {
+        let codes = |password: &str| {
+            let registration = UserRegistration {
+                first_name: Some(String::from("Frodo")),
+                last_name: Some(String::from("Baggins")),
+                email: Some(String::from("frodo@bagend.com")),
+                _password: Some(String::from(password)),
+                _confirm_password: Some(String::from(password)),
+            };
+            let errors = registration.validate().err().unwrap_or_default();
+            let mut codes: Vec<String> = errors
+                .field_errors()
+                .get("password")
+                .map(|errors| errors.iter().map(|e| e.code.to_string()).collect())
+                .unwrap_or_default();
+            codes.sort();
+            codes
+        };
+        assert!(codes("Password1!").is_empty());
+        //what JavaScript counts as special and as a number, respectively
+        assert!(codes("Passwordé1").is_empty());
+        assert_eq!(codes("Password١!"), ["password_number"]);
+        assert_eq!(codes("Pass 1!"), ["password_length"]);
+        assert_eq!(codes("Pass word1!"), ["password_spaces"]);
+        assert!(PASSWORD_PATTERN.contains("(?=.*[0-9])"));
+    }
 }
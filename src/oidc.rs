@@ -0,0 +1,356 @@
+use crate::models::{NewUser, NewUserIdentity, User};
+use crate::tokens::random_token;
+use crate::{connect, DbPool};
+use derive_more::Display;
+use diesel::{insert_into, prelude::*};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+///How long the provider's discovery document is reused before it's fetched
+///again.
+const DISCOVERY_TTL: Duration = Duration::from_secs(60 * 60);
+
+///Settings of the OpenID Connect provider behind "Sign in with ...". Only the
+///issuer is configured; endpoints come from the provider's discovery document.
+#[derive(Clone)]
+pub struct OidcConfig {
+    pub provider_name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    ///Shared by the clones of the config, i.e. by every worker.
+    pub discovery: DiscoveryCache,
+}
+
+impl OidcConfig {
+    ///Reads OIDC_ISSUER, OIDC_CLIENT_ID, OIDC_CLIENT_SECRET, OIDC_REDIRECT_URL and
+    ///OIDC_PROVIDER_NAME. Returns `None` when no issuer/client id is configured.
+    pub fn from_env() -> Option<OidcConfig> {
+        let issuer = env::var("OIDC_ISSUER").ok()?;
+        let client_id = env::var("OIDC_CLIENT_ID").ok()?;
+        Some(OidcConfig {
+            provider_name: env::var("OIDC_PROVIDER_NAME").unwrap_or_else(|_| String::from("SSO")),
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_url: env::var("OIDC_REDIRECT_URL")
+                .unwrap_or_else(|_| format!("{}/login/oidc/callback", crate::app_url())),
+            discovery: DiscoveryCache::default(),
+        })
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum OidcError {
+    #[display(fmt = "Error contacting the identity provider: {}", _0)]
+    Http(String),
+    #[display(fmt = "Invalid ID token: {}", _0)]
+    InvalidToken(String),
+    #[display(fmt = "The identity provider did not share an email address")]
+    MissingEmail,
+    #[display(fmt = "The identity provider has not verified this email address")]
+    UnverifiedEmail,
+    #[display(fmt = "Database error: {}", _0)]
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for OidcError {
+    fn from(e: diesel::result::Error) -> Self {
+        OidcError::Database(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+///The last discovery document fetched, kept for [`DISCOVERY_TTL`].
+#[derive(Clone, Default)]
+pub struct DiscoveryCache(Arc<RwLock<Option<Discovery>>>);
+
+struct Discovery {
+    fetched_at: Instant,
+    metadata: Arc<ProviderMetadata>,
+}
+
+impl DiscoveryCache {
+    fn get(&self) -> Option<Arc<ProviderMetadata>> {
+        self.0
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|discovery| discovery.fetched_at.elapsed() < DISCOVERY_TTL)
+            .map(|discovery| discovery.metadata.clone())
+    }
+
+    fn set(&self, metadata: Arc<ProviderMetadata>) {
+        *self.0.write().unwrap() = Some(Discovery {
+            fetched_at: Instant::now(),
+            metadata,
+        });
+    }
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kid: Option<String>,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+}
+
+///State of an authorization request kept in the session until the provider
+///redirects back to the callback.
+#[derive(Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, OidcError> {
+    let mut response = awc::Client::default()
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| OidcError::Http(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(OidcError::Http(format!(
+            "{url} returned {}",
+            response.status()
+        )));
+    };
+    response
+        .json::<T>()
+        .await
+        .map_err(|e| OidcError::Http(e.to_string()))
+}
+
+async fn discover(config: &OidcConfig) -> Result<Arc<ProviderMetadata>, OidcError> {
+    if let Some(metadata) = config.discovery.get() {
+        return Ok(metadata);
+    };
+    let url = format!("{}/.well-known/openid-configuration", config.issuer);
+    let metadata = get_json::<ProviderMetadata>(&url).await?;
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        return Err(OidcError::InvalidToken(String::from(
+            "discovery document issuer mismatch",
+        )));
+    };
+    let metadata = Arc::new(metadata);
+    config.discovery.set(metadata.clone());
+    Ok(metadata)
+}
+
+///S256 code challenge for a PKCE code verifier (RFC 7636).
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+///Builds the provider's authorization URL along with the state that must be
+///kept until the callback.
+pub async fn authorization_request(
+    config: &OidcConfig,
+) -> Result<(String, PendingLogin), OidcError> {
+    let metadata = discover(config).await?;
+    let pending = PendingLogin {
+        state: random_token(16),
+        nonce: random_token(16),
+        code_verifier: random_token(32),
+    };
+    let query = serde_urlencoded::to_string([
+        ("response_type", "code"),
+        ("client_id", config.client_id.as_str()),
+        ("redirect_uri", config.redirect_url.as_str()),
+        ("scope", "openid email profile"),
+        ("state", pending.state.as_str()),
+        ("nonce", pending.nonce.as_str()),
+        (
+            "code_challenge",
+            pkce_challenge(&pending.code_verifier).as_str(),
+        ),
+        ("code_challenge_method", "S256"),
+    ])
+    .unwrap();
+    let separator = if metadata.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    let url = format!("{}{separator}{query}", metadata.authorization_endpoint);
+    Ok((url, pending))
+}
+
+///Redeems the authorization code and returns the validated ID token claims.
+pub async fn exchange_code(
+    config: &OidcConfig,
+    code: &str,
+    pending: &PendingLogin,
+) -> Result<IdTokenClaims, OidcError> {
+    let metadata = discover(config).await?;
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.redirect_url.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", pending.code_verifier.as_str()),
+    ];
+    if let Some(client_secret) = &config.client_secret {
+        form.push(("client_secret", client_secret.as_str()));
+    };
+    let mut response = awc::Client::default()
+        .post(&metadata.token_endpoint)
+        .send_form(&form)
+        .await
+        .map_err(|e| OidcError::Http(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(OidcError::Http(format!(
+            "token endpoint returned {}",
+            response.status()
+        )));
+    };
+    let tokens = response
+        .json::<TokenResponse>()
+        .await
+        .map_err(|e| OidcError::Http(e.to_string()))?;
+    let jwks = get_json::<Jwks>(&metadata.jwks_uri).await?;
+    validate_id_token(config, &tokens.id_token, &jwks, &pending.nonce)
+}
+
+fn validate_id_token(
+    config: &OidcConfig,
+    id_token: &str,
+    jwks: &Jwks,
+    nonce: &str,
+) -> Result<IdTokenClaims, OidcError> {
+    let invalid = |e: &dyn std::fmt::Display| OidcError::InvalidToken(e.to_string());
+    let header = decode_header(id_token).map_err(|e| invalid(&e))?;
+    if header.alg != Algorithm::RS256 {
+        return Err(invalid(&format!("unsupported algorithm {:?}", header.alg)));
+    };
+    let jwk = jwks
+        .keys
+        .iter()
+        .filter(|key| key.kty == "RSA")
+        .find(|key| header.kid.is_none() || key.kid == header.kid)
+        .ok_or_else(|| invalid(&"no matching signing key"))?;
+    let (n, e) = match (&jwk.n, &jwk.e) {
+        (Some(n), Some(e)) => (n, e),
+        _ => return Err(invalid(&"malformed signing key")),
+    };
+    let key = DecodingKey::from_rsa_components(n, e).map_err(|e| invalid(&e))?;
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_issuer(&[&config.issuer]);
+    validation.set_audience(&[&config.client_id]);
+    let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+        .map_err(|e| invalid(&e))?
+        .claims;
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(invalid(&"nonce mismatch"));
+    };
+    Ok(claims)
+}
+
+///Finds the user linked to the external identity. Unknown identities are linked
+///to the user with the same verified email, or to a newly created user. Users
+///created here get an unusable password and can only log in through the provider
+///or a magic link.
+pub fn link_or_create_user(
+    pool: &DbPool,
+    provider: &str,
+    claims: &IdTokenClaims,
+) -> Result<i32, OidcError> {
+    use crate::schema::user_identities::dsl as identities;
+    use crate::schema::users::dsl as users;
+    let conn = &mut connect(pool)?;
+    conn.transaction(|conn| {
+        let linked = identities::user_identities
+            .select(identities::user_id)
+            .filter(identities::provider.eq(provider))
+            .filter(identities::subject.eq(&claims.sub))
+            .first::<i32>(conn)
+            .optional()?;
+        if let Some(user_id) = linked {
+            return Ok(user_id);
+        };
+        let email = claims.email.clone().ok_or(OidcError::MissingEmail)?;
+        let existing = users::users
+            .filter(users::email.eq(&email))
+            .first::<User>(conn)
+            .optional()?;
+        let user_id = match existing {
+            Some(user) if claims.email_verified == Some(true) => user.id,
+            //an unverified email must not take over an existing account
+            Some(_) => return Err(OidcError::UnverifiedEmail),
+            None => {
+                let local_part = email.split('@').next().unwrap_or_default().to_string();
+                let new_user = NewUser {
+                    first_name: claims.given_name.clone().unwrap_or(local_part),
+                    last_name: claims.family_name.clone().unwrap_or_default(),
+                    email: email.clone(),
+                    password: String::from("!"),
+                };
+                insert_into(users::users)
+                    .values(new_user)
+                    .returning(users::id)
+                    .get_result::<i32>(conn)?
+            }
+        };
+        let identity = NewUserIdentity {
+            user_id,
+            provider: provider.to_string(),
+            subject: claims.sub.clone(),
+            email: Some(email),
+        };
+        insert_into(identities::user_identities)
+            .values(identity)
+            .execute(conn)?;
+        Ok(user_id)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkce_challenge() {
+        //example from RFC 7636 appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
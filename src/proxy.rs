@@ -0,0 +1,308 @@
+//! Client address and scheme behind reverse proxies. `Forwarded`,
+//! `X-Forwarded-For` and `X-Forwarded-Proto` are only believed when the peer
+//! that sent them is a trusted proxy, and the forwarded chain is only followed
+//! through trusted hops: anyone can send these headers, so actix's own
+//! `ConnectionInfo`, which always believes them, mustn't be used for anything
+//! security related.
+use actix_web::{
+    dev::Payload,
+    http::header::{self, HeaderMap},
+    web, FromRequest, HttpRequest,
+};
+use ipnet::IpNet;
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+///Networks of the reverse proxies in front of the app. Empty, the default,
+///trusts no one and the peer address is the client's.
+#[derive(Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<IpNet>>);
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNet>) -> TrustedProxies {
+        TrustedProxies(Arc::new(networks))
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        //IPv4 clients of dual stack sockets show up as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(*ip, IpAddr::V4),
+            IpAddr::V4(_) => *ip,
+        };
+        self.0.iter().any(|network| network.contains(&ip))
+    }
+}
+
+///Parses a CIDR, e.g. `10.0.0.0/8` or `fd00::/8`. A bare address is a network
+///of its own.
+pub fn parse_network(value: &str) -> Result<IpNet, String> {
+    let value = value.trim();
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("{value} is neither an IP address nor a CIDR"))
+}
+
+///One hop of the forwarded chain: the address a proxy received the request
+///from, and the scheme it was received over.
+struct Hop {
+    ip: Option<IpAddr>,
+    proto: Option<String>,
+}
+
+///Address of a `for=` or `X-Forwarded-For` value, e.g. `"[2001:db8::1]:4711"`
+///or `192.0.2.43:47011`. Obfuscated identifiers and `unknown` have none.
+fn parse_node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    };
+    value
+        .parse()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+///Hops of the `Forwarded` header (RFC 7239), client first.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Hop> {
+    let mut hops = vec![];
+    for value in headers.get_all(header::FORWARDED) {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        for element in value.split(',') {
+            let mut hop = Hop {
+                ip: None,
+                proto: None,
+            };
+            for pair in element.split(';') {
+                let (key, value) = match pair.split_once('=') {
+                    Some(pair) => pair,
+                    None => continue,
+                };
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => hop.ip = parse_node(value),
+                    "proto" => hop.proto = Some(value.trim().trim_matches('"').to_lowercase()),
+                    _ => (),
+                };
+            }
+            hops.push(hop);
+        }
+    }
+    hops
+}
+
+fn header_list(headers: &HeaderMap, name: &str) -> Vec<String> {
+    headers
+        .get_all(name)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_string())
+        .collect()
+}
+
+///Hops of `X-Forwarded-For`, client first. `X-Forwarded-Proto` lists a scheme
+///per hop when every proxy appends to it, otherwise its last value is the
+///scheme the client used.
+fn x_forwarded_hops(headers: &HeaderMap) -> Vec<Hop> {
+    let addresses = header_list(headers, "x-forwarded-for");
+    let protos = header_list(headers, "x-forwarded-proto");
+    let aligned = protos.len() == addresses.len();
+    addresses
+        .iter()
+        .enumerate()
+        .map(|(i, address)| Hop {
+            ip: parse_node(address),
+            proto: if aligned {
+                Some(protos[i].to_lowercase())
+            } else {
+                protos.last().map(|proto| proto.to_lowercase())
+            },
+        })
+        .collect()
+}
+
+///Where a request came from, as far as the trusted proxies can tell.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    ///Missing for requests that didn't come over a socket, e.g. in tests.
+    pub ip: Option<IpAddr>,
+    ///`http` or `https`.
+    pub scheme: String,
+}
+
+impl ClientInfo {
+    pub fn of(req: &HttpRequest) -> ClientInfo {
+        let trusted = req
+            .app_data::<web::Data<TrustedProxies>>()
+            .map(|trusted| trusted.get_ref().clone())
+            .unwrap_or_default();
+        ClientInfo::resolve(
+            req.peer_addr().map(|addr| addr.ip()),
+            req.app_config().secure(),
+            req.headers(),
+            &trusted,
+        )
+    }
+
+    ///Walks the forwarded chain back from the peer, as long as the hop that
+    ///reported the next address is trusted. `Forwarded` takes precedence over
+    ///the `X-Forwarded-*` headers.
+    fn resolve(
+        peer: Option<IpAddr>,
+        secure: bool,
+        headers: &HeaderMap,
+        trusted: &TrustedProxies,
+    ) -> ClientInfo {
+        let mut client = ClientInfo {
+            ip: peer,
+            scheme: String::from(if secure { "https" } else { "http" }),
+        };
+        if !peer.is_some_and(|peer| trusted.contains(&peer)) {
+            return client;
+        };
+        let mut hops = forwarded_hops(headers);
+        if hops.is_empty() {
+            hops = x_forwarded_hops(headers);
+        };
+        for hop in hops.into_iter().rev() {
+            //an obfuscated or garbled address ends the chain at the proxy
+            //that reported it
+            let ip = match hop.ip {
+                Some(ip) => ip,
+                None => break,
+            };
+            client.ip = Some(ip);
+            if let Some(proto @ ("http" | "https")) = hop.proto.as_deref() {
+                client.scheme = proto.to_string();
+            };
+            if !trusted.contains(&ip) {
+                break;
+            };
+        }
+        client
+    }
+
+    pub fn is_https(&self) -> bool {
+        self.scheme == "https"
+    }
+}
+
+impl FromRequest for ClientInfo {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(ClientInfo::of(req)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn proxies(networks: &[&str]) -> TrustedProxies {
+        TrustedProxies::new(
+            networks
+                .iter()
+                .map(|network| parse_network(network).unwrap())
+                .collect(),
+        )
+    }
+
+    fn client(peer: &str, headers: &[(&str, &str)], trusted: &[&str]) -> ClientInfo {
+        let mut req = TestRequest::default()
+            .peer_addr(SocketAddr::new(peer.parse().unwrap(), 4711))
+            .app_data(web::Data::new(proxies(trusted)));
+        for header in headers {
+            req = req.append_header(*header);
+        }
+        ClientInfo::of(&req.to_http_request())
+    }
+
+    fn ip(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    #[test]
+    fn test_parse_network() {
+        assert!(parse_network("10.0.0.0/8")
+            .unwrap()
+            .contains(&"10.1.2.3".parse::<IpAddr>().unwrap()));
+        assert_eq!(
+            parse_network(" 192.0.2.1 ").unwrap(),
+            "192.0.2.1/32".parse::<IpNet>().unwrap()
+        );
+        assert_eq!(
+            parse_network("fd00::1").unwrap(),
+            "fd00::1/128".parse::<IpNet>().unwrap()
+        );
+        assert!(parse_network("proxy.internal").is_err());
+    }
+
+    #[test]
+    fn test_ignores_headers_from_untrusted_peers() {
+        let headers = [
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-forwarded-proto", "https"),
+            ("forwarded", "for=203.0.113.7;proto=https"),
+        ];
+        let info = client("198.51.100.1", &headers, &[]);
+        assert_eq!(info.ip, ip("198.51.100.1"));
+        assert_eq!(info.scheme, "http");
+        let info = client("198.51.100.1", &headers, &["10.0.0.0/8"]);
+        assert_eq!(info.ip, ip("198.51.100.1"));
+        assert!(!info.is_https());
+    }
+
+    #[test]
+    fn test_x_forwarded_headers_from_trusted_proxies() {
+        let info = client(
+            "10.0.0.2",
+            &[
+                ("x-forwarded-for", "203.0.113.7, 10.0.0.1"),
+                ("x-forwarded-proto", "https"),
+            ],
+            &["10.0.0.0/8"],
+        );
+        assert_eq!(info.ip, ip("203.0.113.7"));
+        assert!(info.is_https());
+
+        //addresses left of the first untrusted hop could be made up by the client
+        let info = client(
+            "10.0.0.2",
+            &[("x-forwarded-for", "192.0.2.66, 203.0.113.7, 10.0.0.1")],
+            &["10.0.0.0/8"],
+        );
+        assert_eq!(info.ip, ip("203.0.113.7"));
+        assert_eq!(info.scheme, "http");
+    }
+
+    #[test]
+    fn test_forwarded_takes_precedence() {
+        let info = client(
+            "::ffff:10.0.0.2",
+            &[
+                (
+                    "forwarded",
+                    r#"for="[2001:db8::7]:4711";proto=https, for=10.0.0.1;proto=http"#,
+                ),
+                ("x-forwarded-for", "192.0.2.66"),
+            ],
+            &["10.0.0.0/8"],
+        );
+        assert_eq!(info.ip, ip("2001:db8::7"));
+        assert_eq!(info.scheme, "https");
+
+        let info = client(
+            "fd00::2",
+            &[("forwarded", "for=_hidden, for=192.0.2.43:47011")],
+            &["fd00::/8", "192.0.2.43"],
+        );
+        assert_eq!(info.ip, ip("192.0.2.43"));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::models::{NewRememberToken, RememberToken};
+use crate::tokens::{hash_matches, hash_token, random_token};
+use crate::{app_pool, connect, DbPool};
+use actix_identity::{Identity, IdentityExt};
+use actix_web::{
+    body::MessageBody,
+    cookie::{time::Duration, Cookie, SameSite},
+    dev::{ServiceRequest, ServiceResponse},
+    web, Error, HttpMessage, HttpRequest,
+};
+use actix_web_lab::middleware::Next;
+use chrono::Utc;
+use diesel::{delete, insert_into, prelude::*};
+
+pub const COOKIE_NAME: &str = "remember_token";
+const TOKEN_LIFETIME_DAYS: i64 = 30;
+
+///Selector of a token issued while handling the current request, so handlers
+///(e.g. logout) can revoke it even though the browser hasn't received it yet.
+#[derive(Clone)]
+struct RotatedSelector(String);
+
+///Creates a remember-me token for the user and returns the cookie holding it.
+///Only the selector and a hash of the validator are stored.
+pub fn issue(pool: &DbPool, user_id: i32) -> QueryResult<Cookie<'static>> {
+    use crate::schema::remember_tokens::dsl::remember_tokens;
+    let selector = random_token(12);
+    let validator = random_token(32);
+    let new_token = NewRememberToken {
+        user_id,
+        selector: selector.clone(),
+        validator_hash: hash_token(&validator),
+        expires_at: (Utc::now() + chrono::Duration::days(TOKEN_LIFETIME_DAYS)).naive_utc(),
+    };
+    let conn = &mut connect(pool)?;
+    insert_into(remember_tokens)
+        .values(new_token)
+        .execute(conn)?;
+    Ok(cookie(format!("{selector}:{validator}")))
+}
+
+///Issues a token for a user who just logged in, off the executor. A failure is
+///logged: the user is logged in regardless, just not remembered.
+pub async fn issue_cookie(pool: &DbPool, user_id: i32) -> Option<Cookie<'static>> {
+    let pool = pool.clone();
+    match web::block(move || issue(&pool, user_id)).await {
+        Ok(Ok(cookie)) => Some(cookie),
+        Ok(Err(e)) => {
+            log::error!("Error issuing remember token: {e}");
+            None
+        }
+        Err(e) => {
+            log::error!("Error issuing remember token: {e}");
+            None
+        }
+    }
+}
+
+fn cookie(value: String) -> Cookie<'static> {
+    Cookie::build(COOKIE_NAME, value)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(Duration::days(TOKEN_LIFETIME_DAYS))
+        .finish()
+}
+
+///Cookie that clears the remember-me token in the browser.
+pub fn removal_cookie() -> Cookie<'static> {
+    let mut cookie = cookie(String::new());
+    cookie.make_removal();
+    cookie
+}
+
+///Consumes a remember-me cookie value. A valid token is deleted and replaced by
+///a new one, returning the user id and the new cookie. A known selector with a
+///wrong validator suggests a stolen token, so every token of that user is revoked.
+///`Ok(None)` means the token is no good, errors that the database failed.
+pub fn rotate(pool: &DbPool, value: &str) -> QueryResult<Option<(i32, Cookie<'static>)>> {
+    use crate::schema::remember_tokens::dsl::*;
+    let (token_selector, validator) = match value.split_once(':') {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    let conn = &mut connect(pool)?;
+    let token = remember_tokens
+        .filter(selector.eq(token_selector))
+        .first::<RememberToken>(conn)
+        .optional()?;
+    let token = match token {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    if !hash_matches(&token.validator_hash, validator) {
+        revoke_all(pool, token.user_id)?;
+        return Ok(None);
+    };
+    delete(remember_tokens.find(token.id)).execute(conn)?;
+    if token.expires_at < Utc::now().naive_utc() {
+        return Ok(None);
+    };
+    let new_cookie = issue(pool, token.user_id)?;
+    Ok(Some((token.user_id, new_cookie)))
+}
+
+///Revokes the token held in the request's remember-me cookie, along with any
+///token rotated in by [`restore_session`] during this request. Failures are
+///logged, signing out goes ahead regardless.
+pub async fn revoke(req: &HttpRequest) {
+    use crate::schema::remember_tokens::dsl::*;
+    let mut selectors = vec![];
+    if let Some(cookie) = req.cookie(COOKIE_NAME) {
+        if let Some((token_selector, _)) = cookie.value().split_once(':') {
+            selectors.push(token_selector.to_string());
+        };
+    };
+    if let Some(RotatedSelector(token_selector)) = req.extensions().get::<RotatedSelector>() {
+        selectors.push(token_selector.clone());
+    };
+    if selectors.is_empty() {
+        return;
+    };
+    let pool = match app_pool(req) {
+        Some(pool) => pool,
+        None => {
+            log::error!("Error revoking remember token: the app has no database pool");
+            return;
+        }
+    };
+    let revoked = web::block(move || {
+        let conn = &mut connect(&pool)?;
+        delete(remember_tokens.filter(selector.eq_any(selectors))).execute(conn)
+    })
+    .await;
+    match revoked {
+        Ok(Ok(_)) => (),
+        Ok(Err(e)) => log::error!("Error revoking remember token: {e}"),
+        Err(e) => log::error!("Error revoking remember token: {e}"),
+    };
+}
+
+///Revokes every remember-me token of a user, returning how many there were.
+///Call it whenever the user's password changes.
+pub fn revoke_all(pool: &DbPool, account_id: i32) -> QueryResult<usize> {
+    use crate::schema::remember_tokens::dsl::*;
+    let conn = &mut connect(pool)?;
+    delete(remember_tokens.filter(user_id.eq(account_id))).execute(conn)
+}
+
+///Deletes expired remember-me tokens, returning how many there were. Expired
+///tokens are already refused, this only keeps the table small.
+pub fn purge_expired(pool: &DbPool) -> QueryResult<usize> {
+    use crate::schema::remember_tokens::dsl::*;
+    let conn = &mut connect(pool)?;
+    delete(remember_tokens.filter(expires_at.lt(Utc::now().naive_utc()))).execute(conn)
+}
+
+///Revokes every remember-me token, signing every user out of the devices
+///that only stay signed in through one.
+pub fn revoke_everyone(pool: &DbPool) -> QueryResult<usize> {
+    use crate::schema::remember_tokens::dsl::*;
+    let conn = &mut connect(pool)?;
+    delete(remember_tokens).execute(conn)
+}
+
+///Middleware restoring the identity of users without a session but with a
+///valid remember-me cookie. Must be wrapped inside `IdentityMiddleware`. When
+///the database fails, the request goes on without an identity and the cookie
+///is left alone, so an outage doesn't sign anyone out for good.
+pub async fn restore_session(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut new_cookie = None;
+    if let Some(cookie) = req.cookie(COOKIE_NAME) {
+        let pool = app_pool(req.request());
+        if let (Err(_), Some(pool)) = (req.get_identity(), pool) {
+            let value = cookie.value().to_string();
+            new_cookie = match web::block(move || rotate(&pool, &value)).await {
+                Ok(Ok(Some((user_id, new_cookie)))) => {
+                    Identity::login(&req.extensions(), user_id.to_string())
+                        .map_err(actix_web::error::ErrorInternalServerError)?;
+                    if let Some((token_selector, _)) = new_cookie.value().split_once(':') {
+                        req.extensions_mut()
+                            .insert(RotatedSelector(token_selector.to_string()));
+                    };
+                    Some(new_cookie)
+                }
+                Ok(Ok(None)) => Some(removal_cookie()),
+                Ok(Err(e)) => {
+                    log::error!("Error restoring session from remember token: {e}");
+                    None
+                }
+                Err(e) => {
+                    log::error!("Error restoring session from remember token: {e}");
+                    None
+                }
+            };
+        };
+    };
+    let mut res = next.call(req).await?;
+    if let Some(new_cookie) = new_cookie {
+        //the handler's own cookie (e.g. the removal on logout) takes precedence
+        let handler_set_cookie = res.response().cookies().any(|c| c.name() == COOKIE_NAME);
+        if !handler_set_cookie {
+            res.response_mut().add_cookie(&new_cookie)?;
+        };
+    };
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{DieselUserRepository, UserRepository};
+    use crate::testing;
+
+    #[test]
+    fn test_rotate_replaces_token() {
+        let pool = testing::pool();
+        let user = testing::create_user("user");
+        let cookie = issue(&pool, user.id).unwrap();
+        let (user_id, rotated) = rotate(&pool, cookie.value()).unwrap().unwrap();
+        assert_eq!(user_id, user.id);
+        assert_ne!(cookie.value(), rotated.value());
+        assert!(rotate(&pool, cookie.value()).unwrap().is_none());
+        assert!(rotate(&pool, rotated.value()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_purge_keeps_valid_tokens() {
+        let pool = testing::pool();
+        let cookie = issue(&pool, testing::create_user("user").id).unwrap();
+        purge_expired(&pool).unwrap();
+        assert!(rotate(&pool, cookie.value()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_user_repository_revokes_tokens() {
+        let pool = testing::pool();
+        let user = testing::create_user("user");
+        let cookie = issue(&pool, user.id).unwrap();
+        let users = DieselUserRepository::new(pool.clone());
+        assert_eq!(users.revoke_remember_tokens(user.id).unwrap(), 1);
+        assert!(rotate(&pool, cookie.value()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rotate_rejects_malformed_values() {
+        let pool = testing::pool();
+        assert!(rotate(&pool, "").unwrap().is_none());
+        assert!(rotate(&pool, "no-separator").unwrap().is_none());
+        assert!(rotate(&pool, "unknown:selector").unwrap().is_none());
+    }
+}
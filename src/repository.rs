@@ -0,0 +1,221 @@
+//! Storage of user accounts behind [`UserRepository`], so the services can run
+//! against Postgres in the app and against memory in unit tests.
+use crate::models::{NewUser, User};
+use crate::{connect, pool, remember, DbConnection, DbPool};
+use chrono::Utc;
+use diesel::result::{DatabaseErrorInformation, DatabaseErrorKind, Error};
+use diesel::{delete, insert_into, prelude::*, update};
+use std::sync::Mutex;
+
+///Unique constraint Postgres names for `users.email`.
+pub const USERS_EMAIL_KEY: &str = "users_email_key";
+
+pub trait UserRepository: Send + Sync {
+    fn find(&self, id: i32) -> QueryResult<User>;
+    fn find_by_email(&self, email: &str) -> QueryResult<User>;
+    fn list(&self) -> QueryResult<Vec<User>>;
+    fn create(&self, new_user: NewUser) -> QueryResult<User>;
+    fn set_role(&self, id: i32, role: &str) -> QueryResult<User>;
+    fn set_password(&self, id: i32, password_hash: &str) -> QueryResult<User>;
+    ///Deletes the user, returning whether it existed.
+    fn delete(&self, id: i32) -> QueryResult<bool>;
+    ///Revokes the user's remember-me tokens, returning how many there were.
+    fn revoke_remember_tokens(&self, id: i32) -> QueryResult<usize>;
+}
+
+///Users stored in the database behind the pool it was given.
+pub struct DieselUserRepository {
+    pool: DbPool,
+}
+
+impl DieselUserRepository {
+    pub fn new(pool: DbPool) -> DieselUserRepository {
+        DieselUserRepository { pool }
+    }
+
+    fn connect(&self) -> QueryResult<DbConnection> {
+        connect(&self.pool)
+    }
+}
+
+///Repository on the global pool.
+impl Default for DieselUserRepository {
+    fn default() -> DieselUserRepository {
+        DieselUserRepository::new(pool().clone())
+    }
+}
+
+impl UserRepository for DieselUserRepository {
+    fn find(&self, user_id: i32) -> QueryResult<User> {
+        use crate::schema::users::dsl::*;
+        let conn = &mut self.connect()?;
+        users.find(user_id).first(conn)
+    }
+
+    fn find_by_email(&self, value: &str) -> QueryResult<User> {
+        use crate::schema::users::dsl::*;
+        let conn = &mut self.connect()?;
+        users.filter(email.eq(value)).first(conn)
+    }
+
+    fn list(&self) -> QueryResult<Vec<User>> {
+        use crate::schema::users::dsl::*;
+        let conn = &mut self.connect()?;
+        users.order(id.asc()).load::<User>(conn)
+    }
+
+    fn create(&self, new_user: NewUser) -> QueryResult<User> {
+        use crate::schema::users::dsl::*;
+        let conn = &mut self.connect()?;
+        insert_into(users).values(new_user).get_result::<User>(conn)
+    }
+
+    fn set_role(&self, user_id: i32, new_role: &str) -> QueryResult<User> {
+        use crate::schema::users::dsl::*;
+        let conn = &mut self.connect()?;
+        update(users.find(user_id))
+            .set(role.eq(new_role))
+            .get_result::<User>(conn)
+    }
+
+    fn set_password(&self, user_id: i32, password_hash: &str) -> QueryResult<User> {
+        use crate::schema::users::dsl::*;
+        let conn = &mut self.connect()?;
+        update(users.find(user_id))
+            .set(password.eq(password_hash))
+            .get_result::<User>(conn)
+    }
+
+    ///Tokens and linked identities are removed by the database's cascading
+    ///foreign keys.
+    fn delete(&self, user_id: i32) -> QueryResult<bool> {
+        use crate::schema::users::dsl::*;
+        let conn = &mut self.connect()?;
+        let deleted = delete(users.find(user_id)).execute(conn)?;
+        Ok(deleted > 0)
+    }
+
+    fn revoke_remember_tokens(&self, user_id: i32) -> QueryResult<usize> {
+        remember::revoke_all(&self.pool, user_id)
+    }
+}
+
+///Mimics the error information Postgres reports for a violation of the named
+///unique constraint.
+pub struct UniqueViolation(pub &'static str);
+
+impl DatabaseErrorInformation for UniqueViolation {
+    fn message(&self) -> &str {
+        "duplicate key value violates unique constraint"
+    }
+
+    fn details(&self) -> Option<&str> {
+        None
+    }
+
+    fn hint(&self) -> Option<&str> {
+        None
+    }
+
+    fn table_name(&self) -> Option<&str> {
+        Some("users")
+    }
+
+    fn column_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn constraint_name(&self) -> Option<&str> {
+        Some(self.0)
+    }
+
+    fn statement_position(&self) -> Option<i32> {
+        None
+    }
+}
+
+///Keeps users in a `Vec`, enforcing the same unique email constraint as the
+///database.
+#[derive(Default)]
+pub struct InMemoryUserRepository {
+    users: Mutex<Vec<User>>,
+}
+
+impl UserRepository for InMemoryUserRepository {
+    fn find(&self, id: i32) -> QueryResult<User> {
+        let users = self.users.lock().unwrap();
+        users
+            .iter()
+            .find(|user| user.id == id)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    fn find_by_email(&self, email: &str) -> QueryResult<User> {
+        let users = self.users.lock().unwrap();
+        users
+            .iter()
+            .find(|user| user.email == email)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    fn list(&self) -> QueryResult<Vec<User>> {
+        Ok(self.users.lock().unwrap().clone())
+    }
+
+    fn create(&self, new_user: NewUser) -> QueryResult<User> {
+        let mut users = self.users.lock().unwrap();
+        if users.iter().any(|user| user.email == new_user.email) {
+            return Err(Error::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                Box::new(UniqueViolation(USERS_EMAIL_KEY)),
+            ));
+        };
+        let now = Utc::now().naive_utc();
+        let user = User {
+            id: users.iter().map(|user| user.id).max().unwrap_or(0) + 1,
+            first_name: new_user.first_name,
+            last_name: new_user.last_name,
+            email: new_user.email,
+            password: new_user.password,
+            created_at: now,
+            updated_at: now,
+            role: String::from("user"),
+        };
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    fn set_role(&self, id: i32, role: &str) -> QueryResult<User> {
+        let mut users = self.users.lock().unwrap();
+        let user = users
+            .iter_mut()
+            .find(|user| user.id == id)
+            .ok_or(Error::NotFound)?;
+        user.role = role.to_string();
+        Ok(user.clone())
+    }
+
+    fn set_password(&self, id: i32, password_hash: &str) -> QueryResult<User> {
+        let mut users = self.users.lock().unwrap();
+        let user = users
+            .iter_mut()
+            .find(|user| user.id == id)
+            .ok_or(Error::NotFound)?;
+        user.password = password_hash.to_string();
+        Ok(user.clone())
+    }
+
+    fn delete(&self, id: i32) -> QueryResult<bool> {
+        let mut users = self.users.lock().unwrap();
+        let count = users.len();
+        users.retain(|user| user.id != id);
+        Ok(users.len() < count)
+    }
+
+    ///Remember-me tokens are only ever stored in the database.
+    fn revoke_remember_tokens(&self, _: i32) -> QueryResult<usize> {
+        Ok(0)
+    }
+}
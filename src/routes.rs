@@ -1,51 +1,95 @@
-pub mod home;
+pub mod account;
+pub mod admin;
+pub mod api;
+pub mod health;
+pub mod magic;
+pub mod metrics;
+pub mod oidc;
 use super::{
-    forms::LogRegForm,
+    errors::not_found,
+    forms::{self, Form as HtmlForm},
+    i18n::{Locale, LOCALE_COOKIE},
     models::{UserLogin, UserRegistration},
-    not_allowed, register, render, response, /* HTML,*/ JSON,
+    oidc::OidcConfig,
+    remember, render, response,
+    routing::Endpoint,
+    services::auth::AuthService,
+    DbPool, /* HTML,*/ JSON,
 };
 use actix_identity::Identity;
 use actix_web::{
+    cookie::{time::Duration, Cookie, SameSite},
     http::{self, header, header::HeaderValue, StatusCode},
     web::{self, Form, Json},
-    Either, HttpMessage, HttpRequest, HttpResponse, Responder,
+    Either, HttpRequest, HttpResponse, Responder,
 };
 use actix_web_lab::web::Redirect;
 use serde_json::json;
 use tera::Context;
-use uuid::Uuid;
-use validator::Validate;
 //TODO homepage frontend, Routes
 
 type RegisterNewUser = Either<Json<UserRegistration>, Form<UserRegistration>>;
 type LoginUser = Either<Json<UserLogin>, Form<UserLogin>>;
 
-async fn login_get() -> impl Responder {
-    let login_form = LogRegForm::new("Log In", "/login", "POST");
-    let context = Context::from_serialize(login_form).unwrap();
+fn login_page(oidc_config: Option<web::Data<OidcConfig>>, login_form: HtmlForm) -> HttpResponse {
+    let mut context = Context::from_serialize(login_form).unwrap();
+    if let Some(oidc_config) = oidc_config {
+        context.insert("oidc_provider", &oidc_config.provider_name);
+    };
     render("logReg.html", context)
 }
 
+fn redirect_home(is_form: bool) -> HttpResponse {
+    if is_form {
+        return HttpResponse::SeeOther()
+            .append_header((header::LOCATION, "/home"))
+            .finish();
+    };
+    //mimic 2xx/4xx client-side redirects
+    let mut response = response(303, *JSON, None);
+    response
+        .headers_mut()
+        .append(header::LOCATION, HeaderValue::from_static("/home"));
+    response
+}
+
+async fn login_get(oidc_config: Option<web::Data<OidcConfig>>, locale: Locale) -> impl Responder {
+    login_page(oidc_config, forms::login_form(locale))
+}
+
 async fn login_post(
     req: HttpRequest,
+    pool: web::Data<DbPool>,
+    auth: web::Data<AuthService>,
+    oidc_config: Option<web::Data<OidcConfig>>,
+    locale: Locale,
     login_data: LoginUser,
     user: Option<Identity>,
 ) -> impl Responder {
+    //classic form posts from browsers without JS get pages, not JSON
+    let is_form = matches!(login_data, Either::Right(_));
     if user.is_some() {
-        //mimic 2xx/4xx client-side redirects
-        let mut response = response(303, *JSON, None);
-        response
-            .headers_mut()
-            .append(header::LOCATION, HeaderValue::from_static("/home"));
-        response
-    } else {
-        let login = login_data.into_inner();
-        if let Err(e) = login.validate() {
-            let body = serde_json::to_string(&e).unwrap();
+        return redirect_home(is_form);
+    };
+    let login = login_data.into_inner();
+    let user = match auth.login(&req, &login).await {
+        Ok(user) => user,
+        Err(e) if is_form => {
+            let login_form = forms::login_form(locale)
+                .with_values(&login)
+                .with_errors(&locale.translate_errors(&e));
+            let mut response = login_page(oidc_config, login_form);
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            return response;
+        }
+        Err(e) => {
+            let body = serde_json::to_string(&locale.translate_errors(&e)).unwrap();
             return response(400, *JSON, Some(body));
-        };
-        let id = Uuid::new_v4();
-        Identity::login(&req.extensions(), id.to_string()).unwrap();
+        }
+    };
+    let mut response = if is_form {
+        redirect_home(true)
+    } else {
         let body = json!({ "message": "User Logged In Successfully" }).to_string();
         //mimic 2xx/4xx client-side redirects
         let mut response = response(303, *JSON, Some(body));
@@ -53,39 +97,51 @@ async fn login_post(
             .headers_mut()
             .append(header::LOCATION, HeaderValue::from_static("/home"));
         response
-    }
+    };
+    if login.remember {
+        if let Some(cookie) = remember::issue_cookie(&pool, user.id).await {
+            response.add_cookie(&cookie).unwrap();
+        };
+    };
+    response
 }
 
-async fn register_get() -> impl Responder {
-    let register_form = LogRegForm::new("Register", "/register", "POST");
-    let context = Context::from_serialize(register_form).unwrap();
+async fn register_get(locale: Locale) -> impl Responder {
+    let context = Context::from_serialize(forms::register_form(locale)).unwrap();
     render("logReg.html", context)
 }
 
 async fn register_post(
     req: HttpRequest,
+    auth: web::Data<AuthService>,
+    locale: Locale,
     registration_data: RegisterNewUser,
     user: Option<Identity>,
 ) -> impl Responder {
+    let is_form = matches!(registration_data, Either::Right(_));
     if user.is_some() {
-        //mimic 2xx/4xx client-side redirects
-        let mut response = response(303, *JSON, None);
-        response
-            .headers_mut()
-            .append(header::LOCATION, HeaderValue::from_static("/home"));
-        return response;
+        return redirect_home(is_form);
     };
-    let registration_values = registration_data.into_inner();
-    if let Err(e) = registration_values.validate() {
-        let e = serde_json::to_string(&e).unwrap();
-        return response(400, *JSON, Some(e));
+    let registration = registration_data.into_inner();
+    let register_form = forms::register_form(locale).with_values(&registration);
+    if let Err(e) = auth.register(&req, registration).await {
+        let status = e.status();
+        let errors = locale.translate_errors(&e.into_validation_errors());
+        if is_form {
+            let register_form = register_form.with_errors(&errors);
+            let mut response = render(
+                "logReg.html",
+                Context::from_serialize(register_form).unwrap(),
+            );
+            *response.status_mut() = status;
+            return response;
+        };
+        let e = serde_json::to_string(&errors).unwrap();
+        return response(status.as_u16(), *JSON, Some(e));
     };
-    if let Err(e) = register(registration_values).await {
-        let e = serde_json::to_string(&e).unwrap();
-        return response(400, *JSON, Some(e));
+    if is_form {
+        return redirect_home(true);
     };
-    let id = Uuid::new_v4();
-    Identity::login(&req.extensions(), id.to_string()).unwrap();
     let body = json!({
             "message": "User Registered Successfully"
     })
@@ -98,12 +154,15 @@ async fn register_post(
     response
 }
 
-async fn logout(user: Option<Identity>) -> impl Responder {
-    if let Some(user) = user {
-        user.logout();
-    };
+async fn logout(
+    req: HttpRequest,
+    auth: web::Data<AuthService>,
+    user: Option<Identity>,
+) -> impl Responder {
+    auth.logout(&req, user).await;
     HttpResponse::build(StatusCode::from_u16(302).unwrap())
         .append_header((http::header::LOCATION, "/login"))
+        .cookie(remember::removal_cookie())
         .finish()
 }
 
@@ -111,7 +170,7 @@ async fn index_get() -> impl Responder {
     Redirect::new("/", "/login")
 }
 
-async fn home_get(user: Option<Identity>) -> impl Responder {
+async fn home_get(user: Option<Identity>, locale: Locale) -> impl Responder {
     if user.is_none() {
         //mimic 2xx/4xx client-side redirects
         let mut response = response(303, *JSON, None);
@@ -121,96 +180,72 @@ async fn home_get(user: Option<Identity>) -> impl Responder {
         return response;
     };
     let mut context = Context::new();
-    context.insert("title", "Home");
+    context.insert("title", &locale.t("home-title"));
+    context.insert("lang", &locale);
     render("home.html", context)
 }
 
+///Remembers the language the user picked and sends them back to the page
+///they picked it on.
+async fn locale_get(req: HttpRequest, lang: web::Path<String>) -> HttpResponse {
+    let locale = match Locale::new(&lang) {
+        Some(locale) => locale,
+        None => return not_found(req).await,
+    };
+    //only ever redirect within the site
+    let back = req
+        .headers()
+        .get(header::REFERER)
+        .and_then(|referer| referer.to_str().ok()?.parse::<http::Uri>().ok())
+        .and_then(|referer| Some(referer.path_and_query()?.to_string()))
+        .filter(|path| path.starts_with('/') && !path.starts_with("//"))
+        .unwrap_or_else(|| String::from("/home"));
+    let cookie = Cookie::build(LOCALE_COOKIE, locale.as_str())
+        .path("/")
+        .same_site(SameSite::Lax)
+        .max_age(Duration::days(365))
+        .finish();
+    HttpResponse::SeeOther()
+        .append_header((header::LOCATION, back))
+        .cookie(cookie)
+        .finish()
+}
+
 pub fn index(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::resource("/")
-            .route(web::get().to(index_get))
-            .route(web::to(not_allowed)),
-    )
-    .service(
-        web::resource("/login")
-            .route(web::get().to(login_get))
-            .route(web::post().to(login_post))
-            .route(web::to(not_allowed)),
-    )
-    .service(
-        web::resource("/register")
-            .route(web::get().to(register_get))
-            .route(web::post().to(register_post))
-            .route(web::to(not_allowed)),
-    )
-    .service(
-        web::resource("/logout")
-            .route(web::get().to(logout))
-            .route(web::post().to(logout))
-            .route(web::to(not_allowed)),
-    )
-    .service(
-        web::resource("/home")
-            .route(web::get().to(home_get))
-            .route(web::to(not_allowed)),
-    );
+    cfg.service(Endpoint::new("/").get(index_get))
+        .service(Endpoint::new("/login").get(login_get).post(login_post))
+        .service(
+            Endpoint::new("/register")
+                .get(register_get)
+                .post(register_post),
+        )
+        .service(Endpoint::new("/logout").get(logout).post(logout))
+        .service(Endpoint::new("/home").get(home_get))
+        .service(Endpoint::new("/locale/{lang}").get(locale_get));
+}
+
+///Every page and API route, as mounted by [`crate::app::build_app`].
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.configure(index)
+        .configure(admin::index)
+        .configure(account::index)
+        .configure(magic::index)
+        .configure(oidc::index)
+        .configure(api::index);
 }
 
 #[cfg(test)]
 mod index {
     use super::*;
-    use actix_identity::IdentityMiddleware;
-    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-    use actix_web::body::MessageBody;
-    use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
-    use actix_web::{cookie::Key, middleware::Logger};
-    use actix_web::{test, App, Error};
+    use crate::app::{build_app, AppConfig};
+    use crate::audit;
+    use crate::testing::{self, TEST_PASSWORD};
+    use actix_web::test;
     use std::collections::HashMap;
-    fn start_app() -> App<
-        impl ServiceFactory<
-            ServiceRequest,
-            Response = ServiceResponse<impl MessageBody>,
-            Config = (),
-            InitError = (),
-            Error = Error,
-        >,
-    > {
-        App::new()
-            .wrap(IdentityMiddleware::default())
-            .wrap(Logger::default())
-            .wrap(
-                SessionMiddleware::builder(CookieSessionStore::default(), Key::from(&[0; 64]))
-                    .cookie_secure(false)
-                    .build(),
-            )
-            .service(
-                web::resource("/")
-                    .route(web::get().to(index_get))
-                    .route(web::to(not_allowed)),
-            )
-            .service(
-                web::resource("/login")
-                    .route(web::get().to(login_get))
-                    .route(web::post().to(login_post))
-                    .route(web::to(not_allowed)),
-            )
-            .service(
-                web::resource("/register")
-                    .route(web::get().to(register_get))
-                    .route(web::post().to(register_post))
-                    .route(web::to(not_allowed)),
-            )
-            .service(
-                web::resource("/logout")
-                    .route(web::get().to(logout))
-                    .route(web::post().to(logout))
-                    .route(web::to(not_allowed)),
-            )
-    }
 
     #[actix_web::test]
     async fn test_index_get() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
         let request = test::TestRequest::get().uri("/").to_request();
         let response = test::call_service(&app, request).await;
         assert_eq!(response.status(), 307);
@@ -218,7 +253,7 @@ mod index {
 
     #[actix_web::test]
     async fn test_index_post_no_data() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
         let request = test::TestRequest::post().uri("/").to_request();
         let response = test::call_service(&app, request).await;
         assert_eq!(response.status(), 405);
@@ -226,7 +261,7 @@ mod index {
 
     #[actix_web::test]
     async fn test_login_get() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
         let request = test::TestRequest::get().uri("/login").to_request();
         let response = test::call_service(&app, request).await;
         assert_eq!(response.status(), 200);
@@ -234,7 +269,7 @@ mod index {
 
     #[actix_web::test]
     async fn test_login_post_no_data() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
         let request = test::TestRequest::post().uri("/login").to_request();
         let response = test::call_service(&app, request).await;
         assert_eq!(response.status(), 400);
@@ -242,7 +277,7 @@ mod index {
 
     #[actix_web::test]
     async fn test_invalid_route() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
         let request = test::TestRequest::post()
             .uri("/this_does_not_exist")
             .to_request();
@@ -250,12 +285,160 @@ mod index {
         assert_eq!(response.status(), 404);
     }
 
+    #[actix_web::test]
+    async fn test_errors_are_negotiated() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let request = test::TestRequest::get()
+            .uri("/this_does_not_exist")
+            .insert_header((header::ACCEPT, "application/json"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 404);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "not_found");
+
+        let request = test::TestRequest::delete()
+            .uri("/login")
+            .insert_header((header::ACCEPT, "text/html,application/xhtml+xml"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.headers().get(header::ALLOW).unwrap(), "GET, POST");
+        let body = test::read_body(response).await;
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("Method not allowed"));
+
+        //actix's own plain text extractor errors are negotiated too
+        let request = test::TestRequest::post()
+            .uri("/login")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload("{")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "bad_request");
+    }
+
+    #[actix_web::test]
+    async fn form_login_renders_errors_and_redirects() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let user = testing::create_user("user");
+        let request = test::TestRequest::post()
+            .uri("/login")
+            .set_form([
+                ("email", user.email.as_str()),
+                ("password", "Password12!"),
+                ("remember", "on"),
+            ])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("Invalid Credentials"));
+        assert!(body.contains(&format!(r#"value="{}""#, user.email)));
+        assert!(!body.contains("Password12!"));
+
+        let request = test::TestRequest::post()
+            .uri("/login")
+            .set_form([("email", user.email.as_str()), ("password", TEST_PASSWORD)])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 303);
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/home");
+        assert!(test::read_body(response).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn form_register_renders_field_errors() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let user = testing::create_user("user");
+        let request = test::TestRequest::post()
+            .uri("/register")
+            .set_form([
+                ("first_name", "Meriadoc"),
+                ("last_name", ""),
+                ("email", user.email.as_str()),
+                ("password", "Password1!"),
+                ("confirm_password", "Password2!"),
+            ])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(r#"value="Meriadoc""#));
+        assert!(body.contains("Passwords must match"));
+        assert!(body.contains("Email is already registered"));
+        assert!(!body.contains("Password1!"));
+    }
+
+    #[actix_web::test]
+    async fn register_without_database_is_unavailable() {
+        let config = AppConfig {
+            pool: testing::unreachable_pool(),
+            ..testing::config()
+        };
+        let app = test::init_service(build_app(config)).await;
+        let request = test::TestRequest::post()
+            .uri("/register")
+            .set_json(json!({
+                "first_name": "Meriadoc",
+                "last_name": "Brandybuck",
+                "email": "merry@buckland.com",
+                "password": "Password1!",
+                "confirm_password": "Password1!",
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 503);
+    }
+
+    #[actix_web::test]
+    async fn pages_and_errors_follow_the_locale() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let request = test::TestRequest::post()
+            .uri("/login")
+            .insert_header((header::ACCEPT_LANGUAGE, "fr-FR,fr;q=0.9"))
+            .set_form([("email", "frodo@theshire.com"), ("password", "Wrong1!")])
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(r#"<html lang="fr">"#));
+        assert!(body.contains("Mot de passe"));
+        assert!(body.contains("Identifiants invalides"));
+
+        let request = test::TestRequest::get()
+            .uri("/locale/en")
+            .insert_header((header::REFERER, "http://localhost/register"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 303);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "/register"
+        );
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let request = test::TestRequest::get()
+            .uri("/register")
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .cookie(cookie)
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("Confirm Password"));
+    }
+
     #[actix_web::test]
     async fn correct_login() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
+        let user = testing::create_user("user");
         let data = json!({
-            "email" : "frodo@theshire.com",
-            "password" : "Password1!",
+            "email" : user.email,
+            "password" : TEST_PASSWORD,
         });
         let request = test::TestRequest::post()
             .uri("/login")
@@ -267,9 +450,10 @@ mod index {
 
     #[actix_web::test]
     async fn incorrect_and_valid_password_login() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
+        let user = testing::create_user("user");
         let data = json!({
-            "email" : "frodo@theshire.com",
+            "email" : user.email,
             "password" : "Password12!",
         });
         let request = test::TestRequest::post()
@@ -282,7 +466,7 @@ mod index {
 
     #[actix_web::test]
     async fn incorrect_and_valid_email_login() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
         let data = json!({
             "email" : "frodo@theshire",
             "password" : "Password1!",
@@ -297,7 +481,7 @@ mod index {
 
     #[actix_web::test]
     async fn invalid_email_login() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
         let data = json!({
             "email" : "frodo",
             "password" : "Password1!",
@@ -312,7 +496,7 @@ mod index {
 
     #[actix_web::test]
     async fn invalid_login_email_response_body() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
         let data = json!({
             "email" : "frodo",
             "password" :"Password1!",
@@ -330,9 +514,10 @@ mod index {
 
     #[actix_web::test]
     async fn invalid_login_password_response_body() {
-        let app = test::init_service(start_app()).await;
+        let app = test::init_service(build_app(testing::config())).await;
+        let user = testing::create_user("user");
         let data = json!({
-            "email" : "frodo@theshire.com",
+            "email" : user.email,
             "password" :"Password1",
         });
         let request = test::TestRequest::post()
@@ -347,4 +532,150 @@ mod index {
         let right: HashMap<String, serde_json::Value> = serde_json::from_slice(&response).unwrap();
         assert_eq!(left, right)
     }
+
+    #[actix_web::test]
+    async fn failed_login_is_audited() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let email = format!("{}@theshire.com", uuid::Uuid::new_v4());
+        let data = json!({
+            "email" : email,
+            "password" : "Password1!",
+        });
+        let request = test::TestRequest::post()
+            .uri("/login")
+            .insert_header((header::USER_AGENT, "audit-test"))
+            .set_json(data)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        let filter = audit::AuthEventFilter {
+            email: Some(email.clone()),
+            ..Default::default()
+        };
+        let events = audit::search(&testing::pool(), &filter).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "login");
+        assert_eq!(events[0].outcome, "failure");
+        assert_eq!(events[0].user_agent.as_deref(), Some("audit-test"));
+    }
+
+    #[actix_web::test]
+    async fn auth_events_requires_login() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let request = test::TestRequest::get()
+            .uri("/admin/auth-events")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 303);
+
+        let cookie = testing::login_as(&app, &testing::create_user("user")).await;
+        let request = test::TestRequest::get()
+            .uri("/admin/auth-events")
+            .cookie(cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 403);
+
+        let cookie = testing::login_as(&app, &testing::create_user("admin")).await;
+        let request = test::TestRequest::get()
+            .uri("/admin/auth-events")
+            .cookie(cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn pages_follow_the_locale() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let cookie = testing::login_as(&app, &testing::create_user("admin")).await;
+        for (uri, text) in [
+            ("/home", "Bonjour, utilisateur authentifié !"),
+            ("/account", "Créer un jeton"),
+            ("/admin/auth-events", "Tout événement"),
+        ] {
+            let request = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+                .cookie(cookie.clone())
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), 200);
+            let body = test::read_body(response).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            assert!(body.contains(r#"<html lang="fr">"#));
+            assert!(body.contains(text), "{uri}: {body}");
+        }
+
+        let request = test::TestRequest::post()
+            .uri("/account/tokens")
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .cookie(cookie)
+            .set_form([("name", "cli")])
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("Sélectionnez au moins une portée"));
+    }
+
+    #[actix_web::test]
+    async fn remember_me_restores_session() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let user = testing::create_user("user");
+        let data = json!({
+            "email" : user.email,
+            "password" : TEST_PASSWORD,
+            "remember" : "on",
+        });
+        let request = test::TestRequest::post()
+            .uri("/login")
+            .set_json(data)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 303);
+        let cookie = response
+            .response()
+            .cookies()
+            .find(|c| c.name() == remember::COOKIE_NAME)
+            .unwrap()
+            .into_owned();
+        let request = test::TestRequest::get()
+            .uri("/home")
+            .cookie(cookie.clone())
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+        let rotated = response
+            .response()
+            .cookies()
+            .find(|c| c.name() == remember::COOKIE_NAME)
+            .unwrap();
+        assert_ne!(rotated.value(), cookie.value());
+        let request = test::TestRequest::get()
+            .uri("/home")
+            .cookie(cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 303);
+    }
+
+    #[actix_web::test]
+    async fn remember_me_survives_database_outage() {
+        let config = AppConfig {
+            pool: testing::unreachable_pool(),
+            ..testing::config()
+        };
+        let app = test::init_service(build_app(config)).await;
+        let request = test::TestRequest::get()
+            .uri("/home")
+            .cookie(Cookie::new(remember::COOKIE_NAME, "selector:validator"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        //the cookie is neither rotated nor removed, it works again once the
+        //database is back
+        assert!(response
+            .response()
+            .cookies()
+            .all(|c| c.name() != remember::COOKIE_NAME));
+    }
 }
@@ -0,0 +1,231 @@
+use crate::api_tokens::{self, READ, WRITE};
+use crate::auth::CurrentUser;
+use crate::errors::ApiError;
+use crate::i18n::Locale;
+use crate::models::{ApiTokenRequest, User};
+use crate::routing::Endpoint;
+use crate::{current_user, render, response, DbPool, JSON};
+use actix_identity::Identity;
+use actix_web::{
+    http::header::{self, HeaderValue},
+    web::{self, Form},
+    HttpResponse,
+};
+use tera::Context;
+use validator::Validate;
+
+fn login_redirect() -> HttpResponse {
+    //mimic 2xx/4xx client-side redirects
+    let mut response = response(303, *JSON, None);
+    response
+        .headers_mut()
+        .append(header::LOCATION, HeaderValue::from_static("/login"));
+    response
+}
+
+///Renders the account page in the locale. `error` is the id of the message to
+///show, if any.
+fn account_page(
+    pool: &DbPool,
+    locale: Locale,
+    user: &User,
+    new_token: Option<&str>,
+    error: Option<&str>,
+) -> HttpResponse {
+    let tokens = api_tokens::list(pool, user.id).unwrap_or_default();
+    let mut context = Context::new();
+    context.insert("title", &locale.t("account-title"));
+    context.insert("lang", &locale);
+    context.insert("user", user);
+    context.insert("tokens", &tokens);
+    context.insert("new_token", &new_token);
+    context.insert("error", &error.map(|id| locale.t(id)));
+    render("account.html", context)
+}
+
+async fn account_get(
+    pool: web::Data<DbPool>,
+    user: Option<Identity>,
+    locale: Locale,
+) -> Result<HttpResponse, ApiError> {
+    let response = match current_user(&pool, user).await? {
+        Some(user) => account_page(&pool, locale, &user, None, None),
+        None => login_redirect(),
+    };
+    Ok(response)
+}
+
+async fn tokens_post(
+    pool: web::Data<DbPool>,
+    user: Option<Identity>,
+    locale: Locale,
+    token_data: Form<ApiTokenRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let user = match current_user(&pool, user).await? {
+        Some(user) => user,
+        None => return Ok(login_redirect()),
+    };
+    let token_request = token_data.into_inner();
+    if token_request.validate().is_err() {
+        return Ok(account_page(
+            &pool,
+            locale,
+            &user,
+            None,
+            Some("account-error-name"),
+        ));
+    };
+    let mut scopes = vec![];
+    if token_request.scope_read.is_some() {
+        scopes.push(READ);
+    };
+    if token_request.scope_write.is_some() {
+        scopes.push(WRITE);
+    };
+    if scopes.is_empty() {
+        return Ok(account_page(
+            &pool,
+            locale,
+            &user,
+            None,
+            Some("account-error-scopes"),
+        ));
+    };
+    let expires_in_days = token_request
+        .expires_in_days
+        .and_then(|days| days.parse::<i64>().ok())
+        .filter(|days| *days > 0);
+    let response = match api_tokens::create(
+        &pool,
+        user.id,
+        token_request.name.trim(),
+        &scopes,
+        expires_in_days,
+    ) {
+        Ok((_, token)) => account_page(&pool, locale, &user, Some(&token), None),
+        Err(e) => {
+            log::error!("Error creating API token: {e}");
+            account_page(&pool, locale, &user, None, Some("account-error-create"))
+        }
+    };
+    Ok(response)
+}
+
+async fn token_delete(
+    pool: web::Data<DbPool>,
+    user: Option<Identity>,
+    token_id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let user = match current_user(&pool, user).await? {
+        Some(user) => user,
+        None => return Ok(login_redirect()),
+    };
+    if let Err(e) = api_tokens::revoke(&pool, user.id, token_id.into_inner()) {
+        log::error!("Error revoking API token: {e}");
+    };
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/account"))
+        .finish())
+}
+
+///JSON view of the current user, available to browsers and to API clients
+///holding a token with the `read` scope.
+async fn me_get(user: CurrentUser) -> Result<HttpResponse, ApiError> {
+    user.require_scope(READ)?;
+    let body = serde_json::to_string(&user.user).unwrap();
+    Ok(response(200, *JSON, Some(body)))
+}
+
+pub fn index(cfg: &mut web::ServiceConfig) {
+    cfg.service(Endpoint::new("/account").get(account_get))
+        .service(Endpoint::new("/account/me").get(me_get))
+        .service(Endpoint::new("/account/tokens").post(tokens_post))
+        .service(Endpoint::new("/account/tokens/{id}/delete").post(token_delete));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::negotiate_errors;
+    use crate::testing;
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{cookie::Key, test, App};
+    use actix_web_lab::middleware::from_fn;
+
+    #[actix_web::test]
+    async fn test_me_with_bearer_token() {
+        let pool = testing::pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .wrap(from_fn(negotiate_errors))
+                .wrap(IdentityMiddleware::default())
+                .wrap(
+                    SessionMiddleware::builder(CookieSessionStore::default(), Key::from(&[0; 64]))
+                        .cookie_secure(false)
+                        .build(),
+                )
+                .configure(index),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/account/me").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json; charset=utf-8"
+        );
+
+        //browsers get the error page
+        let request = test::TestRequest::get()
+            .uri("/account/me")
+            .insert_header((header::ACCEPT, "text/html"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/html; charset=utf-8"
+        );
+
+        let user = testing::create_user("user");
+        let (read_token, token) =
+            api_tokens::create(&pool, user.id, "read", &[READ], None).unwrap();
+        let request = test::TestRequest::get()
+            .uri("/account/me")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["email"], user.email);
+        assert!(body.get("password").is_none());
+
+        let (write_token, token) =
+            api_tokens::create(&pool, user.id, "write", &[WRITE], None).unwrap();
+        let request = test::TestRequest::get()
+            .uri("/account/me")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 403);
+
+        api_tokens::revoke(&pool, user.id, read_token.id).unwrap();
+        api_tokens::revoke(&pool, user.id, write_token.id).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_me_without_database() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(testing::unreachable_pool()))
+                .configure(index),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri("/account/me")
+            .insert_header((header::AUTHORIZATION, "Bearer wa_unknown"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 503);
+    }
+}
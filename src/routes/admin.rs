@@ -0,0 +1,79 @@
+use crate::audit::{self, AuthEventFilter};
+use crate::errors::ApiError;
+use crate::i18n::Locale;
+use crate::routing::Endpoint;
+use crate::{current_user, render, response, DbPool, HTML, JSON};
+use actix_identity::Identity;
+use actix_web::{
+    http::header::{self, HeaderValue},
+    web, HttpRequest, HttpResponse,
+};
+use tera::Context;
+
+///Returns the response for anyone who isn't an admin: anonymous users are sent to
+///the login page and everyone else gets a 403.
+async fn reject_non_admin(
+    pool: &DbPool,
+    user: Option<Identity>,
+) -> Result<Option<HttpResponse>, ApiError> {
+    let response = match current_user(pool, user).await? {
+        Some(user) if user.is_admin() => None,
+        Some(_) => Some(response(
+            403,
+            *HTML,
+            Some(String::from("<h1>403</h1><p>Forbidden</p>")),
+        )),
+        None => {
+            //mimic 2xx/4xx client-side redirects
+            let mut response = response(303, *JSON, None);
+            response
+                .headers_mut()
+                .append(header::LOCATION, HeaderValue::from_static("/login"));
+            Some(response)
+        }
+    };
+    Ok(response)
+}
+
+async fn auth_events_get(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    user: Option<Identity>,
+    locale: Locale,
+    filter: web::Query<AuthEventFilter>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(response) = reject_non_admin(&pool, user).await? {
+        return Ok(response);
+    };
+    let events = audit::search(&pool, &filter).unwrap_or_default();
+    let mut context = Context::new();
+    context.insert("title", &locale.t("auth-events-title"));
+    context.insert("lang", &locale);
+    context.insert("events", &events);
+    context.insert("filter", &filter.into_inner());
+    context.insert("query", req.query_string());
+    Ok(render("auth_events.html", context))
+}
+
+async fn auth_events_csv(
+    pool: web::Data<DbPool>,
+    user: Option<Identity>,
+    filter: web::Query<AuthEventFilter>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(response) = reject_non_admin(&pool, user).await? {
+        return Ok(response);
+    };
+    let events = audit::search(&pool, &filter).unwrap_or_default();
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .append_header((
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"auth_events.csv\"",
+        ))
+        .body(audit::to_csv(&events)))
+}
+
+pub fn index(cfg: &mut web::ServiceConfig) {
+    cfg.service(Endpoint::new("/admin/auth-events").get(auth_events_get))
+        .service(Endpoint::new("/admin/auth-events.csv").get(auth_events_csv));
+}
@@ -0,0 +1,398 @@
+//! Versioned JSON API. Handlers share the service layer with the HTML routes
+//! and report every failure with the [`ApiError`] envelope.
+use crate::api_tokens::{READ, WRITE};
+use crate::auth::CurrentUser;
+use crate::errors::{ApiError, ErrorBody, ErrorEnvelope};
+use crate::models::{User, UserLogin, UserRegistration, UserUpdate};
+use crate::routing::{self, Endpoint};
+use crate::services::{auth::AuthService, users::UserService};
+use crate::{remember, DbPool};
+use actix_identity::Identity;
+use actix_web::{
+    error::{JsonPayloadError, PathError},
+    http::header,
+    web::{self, Json},
+    Error, HttpRequest, HttpResponse,
+};
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+use validator::Validate;
+
+pub const PREFIX: &str = "/api/v1";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "web_app API"),
+    paths(login, register, logout, me, users_list, user_get, user_update, user_delete),
+    components(schemas(User, UserLogin, UserRegistration, UserUpdate, ErrorEnvelope, ErrorBody)),
+    modifiers(&SecurityAddon),
+    tags((name = "auth", description = "Session login and registration"), (name = "users", description = "User accounts"))
+)]
+pub struct ApiDoc;
+
+///Documents the two ways of authenticating: the session cookie set by login
+///and personal access tokens sent as bearer tokens.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.as_mut().unwrap();
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(utoipa::openapi::security::ApiKey::Cookie(
+                utoipa::openapi::security::ApiKeyValue::new("id"),
+            )),
+        );
+    }
+}
+
+async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+fn json_error(e: JsonPayloadError, _: &HttpRequest) -> Error {
+    ApiError::bad_request(e.to_string()).into()
+}
+
+fn path_error(_: PathError, _: &HttpRequest) -> Error {
+    ApiError::not_found().into()
+}
+
+async fn api_not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::not_found())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/login",
+    tag = "auth",
+    request_body = UserLogin,
+    responses(
+        (status = 200, description = "Logged in, session cookie set", body = User),
+        (status = 400, description = "Invalid credentials", body = ErrorEnvelope)
+    )
+)]
+async fn login(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    auth: web::Data<AuthService>,
+    login: Json<UserLogin>,
+) -> Result<HttpResponse, ApiError> {
+    let user = auth.login(&req, &login).await?;
+    let mut response = HttpResponse::Ok();
+    if login.remember {
+        if let Some(cookie) = remember::issue_cookie(&pool, user.id).await {
+            response.cookie(cookie);
+        };
+    };
+    Ok(response.json(user))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/register",
+    tag = "auth",
+    request_body = UserRegistration,
+    responses(
+        (status = 201, description = "Registered and logged in", body = User),
+        (status = 400, description = "Validation failed", body = ErrorEnvelope),
+        (status = 503, description = "Database unavailable", body = ErrorEnvelope)
+    )
+)]
+async fn register(
+    req: HttpRequest,
+    auth: web::Data<AuthService>,
+    registration: Json<UserRegistration>,
+) -> Result<HttpResponse, ApiError> {
+    let user = auth.register(&req, registration.into_inner()).await?;
+    Ok(HttpResponse::Created()
+        .append_header((header::LOCATION, format!("{PREFIX}/users/{}", user.id)))
+        .json(user))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/logout",
+    tag = "auth",
+    responses((status = 204, description = "Logged out"))
+)]
+async fn logout(
+    req: HttpRequest,
+    auth: web::Data<AuthService>,
+    identity: Option<Identity>,
+) -> HttpResponse {
+    auth.logout(&req, identity).await;
+    HttpResponse::NoContent()
+        .cookie(remember::removal_cookie())
+        .finish()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/me",
+    tag = "users",
+    security(("session" = []), ("bearer" = ["read"])),
+    responses(
+        (status = 200, description = "The current user", body = User),
+        (status = 401, description = "Not authenticated", body = ErrorEnvelope),
+        (status = 403, description = "Token lacks the read scope", body = ErrorEnvelope)
+    )
+)]
+async fn me(user: CurrentUser) -> Result<HttpResponse, ApiError> {
+    user.require_scope(READ)?;
+    Ok(HttpResponse::Ok().json(user.user))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/users",
+    tag = "users",
+    security(("session" = []), ("bearer" = ["read"])),
+    responses(
+        (status = 200, description = "All users", body = [User]),
+        (status = 401, description = "Not authenticated", body = ErrorEnvelope),
+        (status = 403, description = "Not an admin", body = ErrorEnvelope)
+    )
+)]
+async fn users_list(
+    user: CurrentUser,
+    users: web::Data<UserService>,
+) -> Result<HttpResponse, ApiError> {
+    user.require_scope(READ)?;
+    user.require_admin()?;
+    Ok(HttpResponse::Ok().json(users.list()?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security(("session" = []), ("bearer" = ["read"])),
+    responses(
+        (status = 200, description = "The user", body = User),
+        (status = 403, description = "Not an admin or the user themselves", body = ErrorEnvelope),
+        (status = 404, description = "No such user", body = ErrorEnvelope)
+    )
+)]
+async fn user_get(
+    user: CurrentUser,
+    users: web::Data<UserService>,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    user.require_scope(READ)?;
+    let id = id.into_inner();
+    if user.user.id != id {
+        user.require_admin()?;
+    };
+    Ok(HttpResponse::Ok().json(users.find(id)?))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    request_body = UserUpdate,
+    security(("session" = []), ("bearer" = ["write"])),
+    responses(
+        (status = 200, description = "The updated user", body = User),
+        (status = 400, description = "Unknown role", body = ErrorEnvelope),
+        (status = 403, description = "Not an admin", body = ErrorEnvelope),
+        (status = 404, description = "No such user", body = ErrorEnvelope)
+    )
+)]
+async fn user_update(
+    user: CurrentUser,
+    users: web::Data<UserService>,
+    id: web::Path<i32>,
+    update: Json<UserUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    user.require_scope(WRITE)?;
+    user.require_admin()?;
+    update.validate()?;
+    let role = update.into_inner().role.unwrap();
+    Ok(HttpResponse::Ok().json(users.set_role(id.into_inner(), &role)?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security(("session" = []), ("bearer" = ["write"])),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 403, description = "Not an admin", body = ErrorEnvelope),
+        (status = 404, description = "No such user", body = ErrorEnvelope)
+    )
+)]
+async fn user_delete(
+    user: CurrentUser,
+    users: web::Data<UserService>,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    user.require_scope(WRITE)?;
+    user.require_admin()?;
+    if !users.delete_user(id.into_inner())? {
+        return Err(ApiError::not_found());
+    };
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn index(cfg: &mut web::ServiceConfig) {
+    cfg.service(Endpoint::new("/api/openapi.json").get(openapi_json))
+        .service(routing::scope(PREFIX, |scope| {
+            scope
+                .app_data(web::JsonConfig::default().error_handler(json_error))
+                .app_data(web::PathConfig::default().error_handler(path_error))
+                .service(Endpoint::new("/auth/login").post(login))
+                .service(Endpoint::new("/auth/register").post(register))
+                .service(Endpoint::new("/auth/logout").post(logout))
+                .service(Endpoint::new("/me").get(me))
+                .service(Endpoint::new("/users").get(users_list))
+                .service(
+                    Endpoint::new("/users/{id}")
+                        .get(user_get)
+                        .patch(user_update)
+                        .delete(user_delete),
+                )
+                .default_service(web::to(api_not_found))
+        }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::build_app;
+    use crate::repository::{DieselUserRepository, UserRepository};
+    use crate::testing;
+    use actix_web::test;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    #[actix_web::test]
+    async fn test_openapi_document() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let request = test::TestRequest::get()
+            .uri("/api/openapi.json")
+            .to_request();
+        let spec: Value = test::call_and_read_body_json(&app, request).await;
+        assert!(spec["paths"]["/api/v1/users/{id}"]["patch"].is_object());
+        let registration = &spec["components"]["schemas"]["UserRegistration"];
+        assert_eq!(registration["properties"]["password"]["minLength"], 8);
+        assert_eq!(registration["properties"]["email"]["format"], "email");
+        assert!(registration["required"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("confirm_password")));
+        let user = &spec["components"]["schemas"]["User"];
+        assert!(user["properties"].get("password").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_error_envelopes() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let user = testing::create_user("user");
+        let request = test::TestRequest::get().uri("/api/v1/me").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 401);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "unauthorized");
+
+        let request = test::TestRequest::get().uri("/api/v1/nowhere").to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["error"]["status"], 404);
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/auth/login")
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["error"]["code"], "method_not_allowed");
+
+        let request = test::TestRequest::post()
+            .uri("/api/v1/auth/login")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload("{")
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["error"]["code"], "bad_request");
+
+        let request = test::TestRequest::post()
+            .uri("/api/v1/auth/login")
+            .set_json(json!({ "email": user.email, "password": "Password12!" }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "validation_failed");
+        assert_eq!(body["error"]["fields"]["__all__"][0]["code"], "invalid");
+    }
+
+    #[actix_web::test]
+    async fn test_register_and_manage_users() {
+        let app = test::init_service(build_app(testing::config())).await;
+        let email = format!("{}@bree.com", Uuid::new_v4());
+        let request = test::TestRequest::post()
+            .uri("/api/v1/auth/register")
+            .set_json(json!({
+                "first_name": "Barliman",
+                "last_name": "Butterbur",
+                "email": email,
+                "password": "Password1!",
+                "confirm_password": "Password1!",
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 201);
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let user: Value = test::read_body_json(response).await;
+        assert_eq!(user["email"], email.as_str());
+        let user_path = format!("/api/v1/users/{}", user["id"]);
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/users")
+            .cookie(cookie.clone())
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 403);
+
+        DieselUserRepository::new(testing::pool())
+            .set_role(user["id"].as_i64().unwrap() as i32, "admin")
+            .unwrap();
+        let request = test::TestRequest::get()
+            .uri("/api/v1/users")
+            .cookie(cookie.clone())
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+
+        let request = test::TestRequest::patch()
+            .uri(&user_path)
+            .cookie(cookie.clone())
+            .set_json(json!({ "role": "wizard" }))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["error"]["fields"]["role"][0]["code"], "role");
+
+        let request = test::TestRequest::delete()
+            .uri(&user_path)
+            .cookie(cookie.clone())
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 204);
+
+        let request = test::TestRequest::get()
+            .uri(&user_path)
+            .cookie(cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -0,0 +1,168 @@
+//! Probes for load balancers and deploy tooling. These routes are registered
+//! outside the session middleware and aren't logged, so frequent polling
+//! neither creates sessions nor floods the logs.
+use crate::migrations::MIGRATIONS;
+use crate::routing::Endpoint;
+use crate::shutdown::Shutdown;
+use crate::{connection_from, templates_loaded, DbPool};
+use actix_web::{web, HttpResponse};
+use chrono::{TimeZone, Utc};
+use diesel_migrations::MigrationHarness;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Serialize)]
+struct Checks {
+    database: &'static str,
+    migrations: &'static str,
+    templates: &'static str,
+}
+
+impl Checks {
+    fn ok(&self) -> bool {
+        [self.database, self.migrations, self.templates]
+            .iter()
+            .all(|check| *check == "ok")
+    }
+}
+
+///Checks a connection out of the app's pool, reporting a failed checkout
+///instead of panicking when the database is down.
+fn check_database(pool: &DbPool) -> (&'static str, &'static str) {
+    let conn = &mut match connection_from(pool) {
+        Ok(conn) => conn,
+        Err(e) => {
+            log::warn!("Readiness check failed to connect: {e}");
+            return ("unavailable", "unknown");
+        }
+    };
+    let migrations = match conn.has_pending_migration(MIGRATIONS) {
+        Ok(false) => "ok",
+        Ok(true) => "pending",
+        Err(e) => {
+            log::warn!("Readiness check failed to read migrations: {e}");
+            "unknown"
+        }
+    };
+    ("ok", migrations)
+}
+
+///The process is up and serving requests.
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+///Ready for traffic: the server isn't shutting down, the database is
+///reachable, its schema is up to date and the templates loaded.
+async fn readyz(pool: web::Data<DbPool>, shutdown: Option<web::Data<Shutdown>>) -> HttpResponse {
+    if shutdown.is_some_and(|shutdown| shutdown.is_started()) {
+        return HttpResponse::ServiceUnavailable().json(json!({ "status": "shutting_down" }));
+    };
+    let (database, migrations) = web::block(move || check_database(&pool))
+        .await
+        .unwrap_or(("unknown", "unknown"));
+    let checks = Checks {
+        database,
+        migrations,
+        templates: if templates_loaded() {
+            "ok"
+        } else {
+            "unavailable"
+        },
+    };
+    let (mut response, status) = match checks.ok() {
+        true => (HttpResponse::Ok(), "ok"),
+        false => (HttpResponse::ServiceUnavailable(), "unavailable"),
+    };
+    response.json(json!({ "status": status, "checks": checks }))
+}
+
+async fn version() -> HttpResponse {
+    let build_time = env!("BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+        .map(|time| time.to_rfc3339());
+    HttpResponse::Ok().json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("GIT_HASH"),
+        "build_time": build_time,
+    }))
+}
+
+pub fn index(cfg: &mut web::ServiceConfig) {
+    cfg.service(Endpoint::new("/healthz").get(healthz))
+        .service(Endpoint::new("/readyz").get(readyz))
+        .service(Endpoint::new("/version").get(version));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::{test, App};
+    use serde_json::Value;
+
+    #[actix_web::test]
+    async fn test_probes() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(testing::pool()))
+                .configure(index),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/healthz").to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["status"], "ok");
+
+        let request = test::TestRequest::get().uri("/readyz").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["checks"]["migrations"], "ok");
+        assert_eq!(body["checks"]["templates"], "ok");
+
+        let request = test::TestRequest::get().uri("/version").to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["git_hash"].is_string() && body["build_time"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn test_unready_once_shutdown_begins() {
+        let shutdown = Shutdown::default();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(testing::pool()))
+                .app_data(web::Data::new(shutdown.clone()))
+                .configure(index),
+        )
+        .await;
+        shutdown.begin();
+        let request = test::TestRequest::get().uri("/readyz").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 503);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["status"], "shutting_down");
+
+        let request = test::TestRequest::get().uri("/healthz").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_unready_without_database() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(testing::unreachable_pool()))
+                .configure(index),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/readyz").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 503);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["checks"]["database"], "unavailable");
+    }
+}
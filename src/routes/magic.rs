@@ -0,0 +1,287 @@
+use crate::audit::{self, AuthEventKind, AuthOutcome};
+use crate::errors::ApiError;
+use crate::forms;
+use crate::i18n::{translate, Locale};
+use crate::mailer::{Email, Mailer};
+use crate::models::{MagicLinkRequest, User};
+use crate::routing::Endpoint;
+use crate::{app_url, magic_link, render, render_message_in, response, DbPool, JSON};
+use actix_identity::Identity;
+use actix_web::{
+    http::{header, StatusCode},
+    web::{self, Form, Json},
+    Either, HttpMessage, HttpRequest, HttpResponse, Responder,
+};
+use diesel::{OptionalExtension, QueryResult};
+use fluent_bundle::FluentArgs;
+use serde_json::json;
+use tera::Context;
+use validator::Validate;
+
+type RequestMagicLink = Either<Json<MagicLinkRequest>, Form<MagicLinkRequest>>;
+
+async fn magic_link_get(locale: Locale) -> impl Responder {
+    let context = Context::from_serialize(forms::magic_link_form(locale)).unwrap();
+    render("logReg.html", context)
+}
+
+async fn magic_link_post(
+    pool: web::Data<DbPool>,
+    request_data: RequestMagicLink,
+    mailer: web::Data<dyn Mailer>,
+    locale: Locale,
+) -> Result<HttpResponse, ApiError> {
+    let is_json = matches!(request_data, Either::Left(_));
+    let link_request = request_data.into_inner();
+    if let Err(e) = link_request.validate() {
+        if is_json {
+            let body = serde_json::to_string(&e).unwrap();
+            return Ok(response(400, *JSON, Some(body)));
+        };
+        return Ok(render_message_in(
+            locale,
+            StatusCode::BAD_REQUEST,
+            &locale.t("login-magic-title"),
+            &locale.t("login-magic-invalid-email"),
+            "/login/magic",
+        ));
+    };
+    let email = link_request.email.unwrap();
+    let created = web::block(move || -> QueryResult<Option<(User, String)>> {
+        let user = match User::find_by_email(&pool, &email).optional()? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        let token = magic_link::create(&pool, user.id)?;
+        Ok(Some((user, token)))
+    });
+    let created = match created.await {
+        Ok(created) => created?,
+        Err(e) => {
+            log::error!("Error creating login link: {e}");
+            return Err(ApiError::internal());
+        }
+    };
+    //respond the same way whether or not the account exists
+    if let Some((user, token)) = created {
+        let mut args = FluentArgs::new();
+        args.set("name", user.first_name);
+        args.set("link", format!("{}/login/magic/{token}", app_url()));
+        let link = Email {
+            to: user.email,
+            subject: locale.t("login-magic-email-subject"),
+            body: translate(locale.as_str(), "login-magic-email-body", Some(&args)),
+        };
+        if let Err(e) = mailer.send(&link) {
+            log::error!("Error sending login link: {e}");
+        };
+    };
+    let sent = locale.t("login-magic-sent");
+    if is_json {
+        let body = json!({ "message": sent }).to_string();
+        return Ok(response(200, *JSON, Some(body)));
+    };
+    Ok(render_message_in(
+        locale,
+        StatusCode::OK,
+        &locale.t("login-magic-title"),
+        &sent,
+        "/login",
+    ))
+}
+
+///Asks the user to confirm before the link is used, see
+///[`forms::magic_link_confirm_form`].
+async fn magic_link_confirm(locale: Locale, token: web::Path<String>) -> impl Responder {
+    let form = forms::magic_link_confirm_form(locale, &token);
+    render("logReg.html", Context::from_serialize(form).unwrap())
+}
+
+async fn magic_link_verify(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    locale: Locale,
+    token: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let token = token.into_inner();
+    let consumed = web::block(move || -> QueryResult<Option<(i32, Option<String>)>> {
+        let user_id = match magic_link::consume(&pool, &token)? {
+            Some(user_id) => user_id,
+            None => return Ok(None),
+        };
+        let email = User::find(&pool, user_id).ok().map(|user| user.email);
+        Ok(Some((user_id, email)))
+    });
+    let consumed = match consumed.await {
+        Ok(consumed) => consumed?,
+        Err(e) => {
+            log::error!("Error consuming login link: {e}");
+            return Err(ApiError::internal());
+        }
+    };
+    let response = match consumed {
+        Some((user_id, email)) => {
+            Identity::login(&req.extensions(), user_id.to_string()).unwrap();
+            audit::record(
+                &req,
+                AuthEventKind::Login,
+                AuthOutcome::Success,
+                Some(user_id),
+                email.as_deref(),
+            );
+            HttpResponse::SeeOther()
+                .append_header((header::LOCATION, "/home"))
+                .finish()
+        }
+        None => {
+            audit::record(&req, AuthEventKind::Login, AuthOutcome::Failure, None, None);
+            render_message_in(
+                locale,
+                StatusCode::BAD_REQUEST,
+                &locale.t("login-magic-title"),
+                &locale.t("login-magic-invalid-link"),
+                "/login/magic",
+            )
+        }
+    };
+    Ok(response)
+}
+
+pub fn index(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        Endpoint::new("/login/magic")
+            .get(magic_link_get)
+            .post(magic_link_post),
+    )
+    .service(
+        Endpoint::new("/login/magic/{token}")
+            .get(magic_link_confirm)
+            .post(magic_link_verify),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailer::MemoryMailer;
+    use crate::testing;
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{cookie::Key, test, App};
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn test_magic_link_login() {
+        let user = testing::create_user("user");
+        let mailer = Arc::new(MemoryMailer::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(testing::pool()))
+                .app_data(web::Data::from(mailer.clone() as Arc<dyn Mailer>))
+                .wrap(IdentityMiddleware::default())
+                .wrap(
+                    SessionMiddleware::builder(CookieSessionStore::default(), Key::from(&[0; 64]))
+                        .cookie_secure(false)
+                        .build(),
+                )
+                .configure(index),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/login/magic")
+            .set_json(json!({ "email": "nobody@theshire.com" }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+        assert!(mailer.sent().is_empty());
+
+        let request = test::TestRequest::post()
+            .uri("/login/magic")
+            .set_form([("email", &user.email)])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, user.email);
+        let link = sent[0]
+            .body
+            .split_whitespace()
+            .find(|word| word.contains("/login/magic/"))
+            .unwrap();
+        let path = &link[link.find("/login/magic/").unwrap()..];
+
+        //opening the link, e.g. by a mail scanner, doesn't use it up
+        for _ in 0..2 {
+            let request = test::TestRequest::get().uri(path).to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), 200);
+            let body = test::read_body(response).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            //tera escapes the slashes of the action
+            let action = path.replace('/', "&#x2F;");
+            assert!(body.contains(&format!(r#"action="{action}""#)));
+            assert!(body.contains(r#"method="POST""#));
+        }
+
+        let request = test::TestRequest::post().uri(path).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 303);
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/home");
+
+        let request = test::TestRequest::post().uri(path).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_magic_link_messages_follow_the_locale() {
+        let user = testing::create_user("user");
+        let mailer = Arc::new(MemoryMailer::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(testing::pool()))
+                .app_data(web::Data::from(mailer.clone() as Arc<dyn Mailer>))
+                .configure(index),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/login/magic")
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .set_json(json!({ "email": user.email }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Si un compte existe"));
+        let sent = mailer.sent();
+        assert_eq!(sent[0].subject, "Votre lien de connexion");
+        assert!(sent[0]
+            .body
+            .starts_with(&format!("Bonjour {},", user.first_name)));
+
+        let request = test::TestRequest::post()
+            .uri("/login/magic")
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .set_form([("email", "not an email")])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("Veuillez saisir une adresse e-mail valide."));
+        assert!(body.contains("Retour"));
+
+        let request = test::TestRequest::post()
+            .uri("/login/magic/not-a-token")
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("Lien de connexion par e-mail"));
+        assert!(body.contains("Ce lien de connexion est invalide ou a expiré."));
+    }
+}
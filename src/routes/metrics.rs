@@ -0,0 +1,16 @@
+use crate::metrics;
+use crate::routing::Endpoint;
+use crate::DbPool;
+use actix_web::{web, HttpResponse};
+
+async fn metrics_get(pool: web::Data<DbPool>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics::render(&pool))
+}
+
+///Like the probes, `/metrics` is served outside the session middleware and
+///isn't counted in the request metrics itself.
+pub fn index(cfg: &mut web::ServiceConfig) {
+    cfg.service(Endpoint::new("/metrics").get(metrics_get));
+}
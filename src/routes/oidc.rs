@@ -0,0 +1,404 @@
+use crate::audit::{self, AuthEventKind, AuthOutcome};
+use crate::errors::{error_response, not_found, ApiError};
+use crate::i18n::{translate, Locale};
+use crate::oidc::{self, OidcConfig, OidcError, PendingLogin};
+use crate::routing::Endpoint;
+use crate::{render_message_in, DbPool};
+use actix_identity::Identity;
+use actix_session::Session;
+use actix_web::{
+    http::{header, StatusCode},
+    web, HttpMessage, HttpRequest, HttpResponse,
+};
+use diesel::result::{DatabaseErrorKind, Error};
+use fluent_bundle::FluentArgs;
+use serde::Deserialize;
+
+const PENDING_LOGIN_KEY: &str = "oidc_pending_login";
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+///Message id of what the user is told about an error the provider redirected
+///back with. Only known codes get a message: anyone can link to the callback
+///with text of their choosing.
+fn provider_error_reason(code: &str) -> &'static str {
+    match code {
+        "access_denied" => "oidc-error-access-denied",
+        "login_required"
+        | "interaction_required"
+        | "consent_required"
+        | "account_selection_required" => "oidc-error-sign-in-there",
+        "server_error" | "temporarily_unavailable" => "oidc-error-unavailable",
+        _ => "oidc-error-provider",
+    }
+}
+
+///Message id of what the user is told about a failed login. Details only go
+///to the log.
+fn failure_reason(e: &OidcError) -> &'static str {
+    match e {
+        OidcError::Http(_) => "oidc-error-unavailable",
+        OidcError::InvalidToken(_) => "oidc-error-invalid-token",
+        OidcError::MissingEmail => "oidc-error-missing-email",
+        OidcError::UnverifiedEmail => "oidc-error-unverified-email",
+        OidcError::Database(_) => "oidc-error-internal",
+    }
+}
+
+///Tells the user why signing in failed. `reason` is a message id.
+fn login_failed(locale: Locale, provider_name: &str, reason: &str) -> HttpResponse {
+    let lang = locale.as_str();
+    let mut args = FluentArgs::new();
+    args.set("provider", provider_name);
+    args.set("reason", translate(lang, reason, None));
+    render_message_in(
+        locale,
+        StatusCode::BAD_REQUEST,
+        &translate(lang, "sign-in-with", Some(&args)),
+        &translate(lang, "oidc-login-failed", Some(&args)),
+        "/login",
+    )
+}
+
+async fn oidc_login(
+    req: HttpRequest,
+    config: Option<web::Data<OidcConfig>>,
+    session: Session,
+    locale: Locale,
+) -> HttpResponse {
+    let config = match config {
+        Some(config) => config,
+        None => return not_found(req).await,
+    };
+    match oidc::authorization_request(&config).await {
+        Ok((url, pending)) => {
+            session.insert(PENDING_LOGIN_KEY, pending).unwrap();
+            HttpResponse::SeeOther()
+                .append_header((header::LOCATION, url))
+                .finish()
+        }
+        Err(e) => {
+            log::error!("Error starting OIDC login: {e}");
+            login_failed(locale, &config.provider_name, "oidc-error-unavailable")
+        }
+    }
+}
+
+async fn oidc_callback(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: Option<web::Data<OidcConfig>>,
+    session: Session,
+    locale: Locale,
+    query: web::Query<CallbackQuery>,
+) -> HttpResponse {
+    let config = match config {
+        Some(config) => config,
+        None => return not_found(req).await,
+    };
+    let pending = session.remove_as::<PendingLogin>(PENDING_LOGIN_KEY);
+    let pending = match pending {
+        Some(Ok(pending)) if query.state.as_ref() == Some(&pending.state) => pending,
+        _ => return login_failed(locale, &config.provider_name, "oidc-error-expired"),
+    };
+    if let Some(error) = &query.error {
+        log::warn!("OIDC provider returned error {error:?}");
+        return login_failed(locale, &config.provider_name, provider_error_reason(error));
+    };
+    let code = match &query.code {
+        Some(code) => code,
+        None => return login_failed(locale, &config.provider_name, "oidc-error-no-code"),
+    };
+    let claims = match oidc::exchange_code(&config, code, &pending).await {
+        Ok(claims) => {
+            let issuer = config.issuer.clone();
+            let linked = web::block(move || {
+                oidc::link_or_create_user(&pool, &issuer, &claims).map(|id| (id, claims))
+            });
+            match linked.await {
+                Ok(linked) => linked,
+                Err(e) => {
+                    log::error!("Error linking OIDC user: {e}");
+                    return error_response(&req, ApiError::internal());
+                }
+            }
+        }
+        Err(e) => Err(e),
+    };
+    match claims {
+        Ok((user_id, claims)) => {
+            Identity::login(&req.extensions(), user_id.to_string()).unwrap();
+            audit::record(
+                &req,
+                AuthEventKind::Login,
+                AuthOutcome::Success,
+                Some(user_id),
+                claims.email.as_deref(),
+            );
+            HttpResponse::SeeOther()
+                .append_header((header::LOCATION, "/home"))
+                .finish()
+        }
+        Err(OidcError::Database(
+            e @ Error::DatabaseError(DatabaseErrorKind::ClosedConnection, _),
+        )) => error_response(&req, ApiError::from(e)),
+        Err(e) => {
+            log::warn!("OIDC login failed: {e}");
+            audit::record(&req, AuthEventKind::Login, AuthOutcome::Failure, None, None);
+            login_failed(locale, &config.provider_name, failure_reason(&e))
+        }
+    }
+}
+
+pub fn index(cfg: &mut web::ServiceConfig) {
+    cfg.service(Endpoint::new("/login/oidc").get(oidc_login))
+        .service(Endpoint::new("/login/oidc/callback").get(oidc_callback));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::User;
+    use crate::testing;
+    use actix_http::Request;
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{
+        body::MessageBody,
+        cookie::{Cookie, Key},
+        dev::{Service, ServiceResponse},
+        test, App, Error, HttpServer,
+    };
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    const CLIENT_ID: &str = "web_app";
+    const KEY_ID: &str = "mock-key";
+    const SIGNING_KEY: &str = include_str!("../../tests/fixtures/oidc_rsa_key.pem");
+    const MODULUS: &str = "0Br8ZG1VkbKxOYwjyGOqtFUhR93c3vrBbIMhY5JyBojxhBJDpyQ2gpxmKONzY1Hk_WV0_M-wUJ6VWqlMjLYviVAXZ5kCZ2B37fs1y57VsKTbUmCmzdnDTmldiU7aFFaSaUV_vHqbh7sdlYS5Z9ZaOI7Ai_EUPblxkCWV0_-CogFV9_LgFUR7ohDZiaHqrgAC3m-Thdl-PsxvCLrXv7MD85aJoUj2ht4viIC4TFhioH4p4LU2B8oyZU_zp33XdbwBXGT8x4pD2xm_iWkGa8M9lsCBT5crASdVWAdGqw0M20wenrk-KGAA5riYewMuPQx-1l68OZ6juh45PziFmkge8w";
+
+    ///Authorization the mock provider has granted: what the user consented to,
+    ///plus the nonce and PKCE challenge of the request.
+    struct Grant {
+        nonce: String,
+        code_challenge: String,
+        subject: String,
+        email: String,
+    }
+
+    struct MockProvider {
+        issuer: String,
+        grants: Mutex<HashMap<String, Grant>>,
+        discoveries: AtomicUsize,
+    }
+
+    async fn discovery(provider: web::Data<MockProvider>) -> HttpResponse {
+        provider.discoveries.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Ok().json(json!({
+            "issuer": provider.issuer,
+            "authorization_endpoint": format!("{}/authorize", provider.issuer),
+            "token_endpoint": format!("{}/token", provider.issuer),
+            "jwks_uri": format!("{}/jwks", provider.issuer),
+        }))
+    }
+
+    async fn jwks() -> HttpResponse {
+        HttpResponse::Ok().json(json!({
+            "keys": [{ "kty": "RSA", "kid": KEY_ID, "alg": "RS256", "use": "sig", "n": MODULUS, "e": "AQAB" }]
+        }))
+    }
+
+    async fn token(
+        provider: web::Data<MockProvider>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> HttpResponse {
+        let grant = form
+            .get("code")
+            .and_then(|code| provider.grants.lock().unwrap().remove(code));
+        let grant = match grant {
+            Some(grant) => grant,
+            None => return HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" })),
+        };
+        let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+        if oidc::pkce_challenge(&verifier) != grant.code_challenge
+            || form.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+        {
+            return HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }));
+        };
+        let now = chrono::Utc::now().timestamp();
+        let claims = json!({
+            "iss": provider.issuer,
+            "aud": CLIENT_ID,
+            "sub": grant.subject,
+            "iat": now,
+            "exp": now + 300,
+            "nonce": grant.nonce,
+            "email": grant.email,
+            "email_verified": true,
+            "given_name": "Samwise",
+            "family_name": "Gamgee",
+        });
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(String::from(KEY_ID));
+        let key = EncodingKey::from_rsa_pem(SIGNING_KEY.as_bytes()).unwrap();
+        let id_token = encode(&header, &claims, &key).unwrap();
+        HttpResponse::Ok()
+            .json(json!({ "access_token": "mock", "token_type": "Bearer", "id_token": id_token }))
+    }
+
+    fn start_mock_provider() -> web::Data<MockProvider> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let provider = web::Data::new(MockProvider {
+            issuer: format!("http://{}", listener.local_addr().unwrap()),
+            grants: Mutex::new(HashMap::new()),
+            discoveries: AtomicUsize::new(0),
+        });
+        let data = provider.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(discovery),
+                )
+                .route("/jwks", web::get().to(jwks))
+                .route("/token", web::post().to(token))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        actix_web::rt::spawn(server);
+        provider
+    }
+
+    ///Starts a login and returns the session cookie and the query parameters
+    ///of the authorization URL the user is sent to.
+    async fn start_login<S, B>(app: &S) -> (Cookie<'static>, HashMap<String, String>)
+    where
+        S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+        B: MessageBody,
+    {
+        let request = test::TestRequest::get().uri("/login/oidc").to_request();
+        let response = test::call_service(app, request).await;
+        assert_eq!(response.status(), 303);
+        let session_cookie = response.response().cookies().next().unwrap().into_owned();
+        let location = response.headers().get(header::LOCATION).unwrap();
+        let (endpoint, query) = location.to_str().unwrap().split_once('?').unwrap();
+        assert!(endpoint.ends_with("/authorize"));
+        (session_cookie, serde_urlencoded::from_str(query).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_oidc_login_with_mock_provider() {
+        let provider = start_mock_provider();
+        let config = OidcConfig {
+            provider_name: String::from("Mock"),
+            issuer: provider.issuer.clone(),
+            client_id: String::from(CLIENT_ID),
+            client_secret: Some(String::from("secret")),
+            redirect_url: String::from("http://localhost/login/oidc/callback"),
+            discovery: Default::default(),
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(testing::pool()))
+                .app_data(web::Data::new(config))
+                .wrap(IdentityMiddleware::default())
+                .wrap(
+                    SessionMiddleware::builder(CookieSessionStore::default(), Key::from(&[0; 64]))
+                        .cookie_secure(false)
+                        .build(),
+                )
+                .configure(index),
+        )
+        .await;
+
+        let (session_cookie, params) = start_login(&app).await;
+        assert_eq!(params["code_challenge_method"], "S256");
+        let request = test::TestRequest::get()
+            .uri("/login/oidc/callback?code=mock-code&state=forged")
+            .cookie(session_cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+
+        let (session_cookie, params) = start_login(&app).await;
+        //the user consents at the provider, which redirects back with a code
+        let email = format!("{}@bagend.com", Uuid::new_v4());
+        provider.grants.lock().unwrap().insert(
+            String::from("mock-code"),
+            Grant {
+                nonce: params["nonce"].clone(),
+                code_challenge: params["code_challenge"].clone(),
+                subject: Uuid::new_v4().to_string(),
+                email: email.clone(),
+            },
+        );
+        let request = test::TestRequest::get()
+            .uri(&format!(
+                "/login/oidc/callback?code=mock-code&state={}",
+                params["state"]
+            ))
+            .cookie(session_cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 303);
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/home");
+
+        let user = User::find_by_email(&testing::pool(), &email).unwrap();
+        assert_eq!(user.first_name, "Samwise");
+        assert_eq!(user.last_name, "Gamgee");
+        //two logins and a code exchange, one discovery
+        assert_eq!(provider.discoveries.load(Ordering::SeqCst), 1);
+
+        //error codes get fixed messages, whatever text comes along
+        for (error, message) in [
+            ("access_denied", "Sign in failed: access was denied"),
+            (
+                "Your+account+is+locked,+call+555-0100",
+                "Sign in failed: the identity provider reported an error",
+            ),
+        ] {
+            let (session_cookie, params) = start_login(&app).await;
+            let request = test::TestRequest::get()
+                .uri(&format!(
+                    "/login/oidc/callback?error={error}&error_description=Call+555-0100&state={}",
+                    params["state"]
+                ))
+                .cookie(session_cookie)
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), 400);
+            let body = test::read_body(response).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            assert!(body.contains(message), "{body}");
+            assert!(!body.contains("555"));
+        }
+
+        let (session_cookie, params) = start_login(&app).await;
+        let request = test::TestRequest::get()
+            .uri(&format!(
+                "/login/oidc/callback?error=temporarily_unavailable&state={}",
+                params["state"]
+            ))
+            .insert_header((header::ACCEPT_LANGUAGE, "fr"))
+            .cookie(session_cookie)
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("Se connecter avec Mock"), "{body}");
+        assert!(body
+            .contains("Échec de la connexion : le fournisseur d&#x27;identité est indisponible"));
+    }
+}
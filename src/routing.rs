@@ -0,0 +1,170 @@
+//! [`Endpoint`] builds a resource while keeping track of the methods it
+//! handles, so requests with any other method get a 405 listing them in the
+//! `Allow` header.
+use crate::errors::method_not_allowed;
+use actix_web::{
+    dev::{AppService, HttpServiceFactory},
+    http::Method,
+    web, FromRequest, Handler, HttpRequest, Resource, Responder, Scope,
+};
+
+#[cfg(test)]
+thread_local! {
+    ///Prefixes of the [`Prefixed`] scopes being registered.
+    static PREFIXES: std::cell::RefCell<Vec<String>> = Default::default();
+    ///Full path and method of every route registered on this thread.
+    static ROUTE_TABLE: std::cell::RefCell<Vec<(String, Method)>> = Default::default();
+}
+
+///Takes the routes registered on this thread since the last call. Apps
+///register their routes when they're initialized.
+#[cfg(test)]
+pub fn take_route_table() -> Vec<(String, Method)> {
+    ROUTE_TABLE.with(|table| table.take())
+}
+
+pub struct Endpoint {
+    #[cfg_attr(not(test), allow(dead_code))]
+    path: String,
+    resource: Resource,
+    methods: Vec<Method>,
+}
+
+impl Endpoint {
+    pub fn new(path: &str) -> Endpoint {
+        Endpoint {
+            path: path.to_string(),
+            resource: web::resource(path),
+            methods: vec![],
+        }
+    }
+
+    pub fn route<F, Args>(mut self, method: Method, handler: F) -> Endpoint
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.methods.push(method.clone());
+        self.resource = self.resource.route(web::method(method).to(handler));
+        self
+    }
+
+    pub fn get<F, Args>(self, handler: F) -> Endpoint
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::GET, handler)
+    }
+
+    pub fn post<F, Args>(self, handler: F) -> Endpoint
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::POST, handler)
+    }
+
+    pub fn patch<F, Args>(self, handler: F) -> Endpoint
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::PATCH, handler)
+    }
+
+    pub fn delete<F, Args>(self, handler: F) -> Endpoint
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::DELETE, handler)
+    }
+
+    ///Value of the `Allow` header, e.g. `GET, POST`.
+    pub fn allow(&self) -> String {
+        let methods: Vec<&str> = self.methods.iter().map(Method::as_str).collect();
+        methods.join(", ")
+    }
+}
+
+impl HttpServiceFactory for Endpoint {
+    fn register(self, config: &mut AppService) {
+        #[cfg(test)]
+        {
+            let prefix = PREFIXES.with(|prefixes| prefixes.borrow().concat());
+            let path = format!("{prefix}{}", self.path);
+            ROUTE_TABLE.with(|table| {
+                for method in &self.methods {
+                    table.borrow_mut().push((path.clone(), method.clone()));
+                }
+            });
+        };
+        let allow = self.allow();
+        let resource = self
+            .resource
+            .default_service(web::to(move |req: HttpRequest| {
+                let allow = allow.clone();
+                async move { method_not_allowed(&req, &allow) }
+            }));
+        resource.register(config);
+    }
+}
+
+///A scope that knows its prefix, so the endpoints in it are listed in the
+///route table under their full path.
+pub struct Prefixed<T> {
+    #[cfg_attr(not(test), allow(dead_code))]
+    prefix: &'static str,
+    scope: Scope<T>,
+}
+
+///Builds the scope mounted at `prefix`.
+pub fn scope<T>(prefix: &'static str, build: impl FnOnce(Scope) -> Scope<T>) -> Prefixed<T> {
+    Prefixed {
+        prefix,
+        scope: build(web::scope(prefix)),
+    }
+}
+
+impl<T> HttpServiceFactory for Prefixed<T>
+where
+    Scope<T>: HttpServiceFactory,
+{
+    fn register(self, config: &mut AppService) {
+        #[cfg(test)]
+        PREFIXES.with(|prefixes| prefixes.borrow_mut().push(self.prefix.to_string()));
+        self.scope.register(config);
+        #[cfg(test)]
+        PREFIXES.with(|prefixes| prefixes.borrow_mut().pop());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::header, test, App, HttpResponse};
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_allow_header() {
+        let endpoint = Endpoint::new("/thing").get(ok).post(ok);
+        assert_eq!(endpoint.allow(), "GET, POST");
+        let app = test::init_service(App::new().service(endpoint)).await;
+        let request = test::TestRequest::delete().uri("/thing").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.headers().get(header::ALLOW).unwrap(), "GET, POST");
+        let request = test::TestRequest::post().uri("/thing").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+    }
+}
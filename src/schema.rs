@@ -1,5 +1,64 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Varchar,
+        token_hash -> Varchar,
+        scopes -> Varchar,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    auth_events (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        email -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Text>,
+        event -> Varchar,
+        outcome -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    login_links (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    remember_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        selector -> Varchar,
+        validator_hash -> Varchar,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        provider -> Varchar,
+        subject -> Varchar,
+        email -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
@@ -9,5 +68,14 @@ diesel::table! {
         password -> Varchar,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        role -> Varchar,
     }
 }
+
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(auth_events -> users (user_id));
+diesel::joinable!(login_links -> users (user_id));
+diesel::joinable!(remember_tokens -> users (user_id));
+diesel::joinable!(user_identities -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(auth_events, users,);
@@ -0,0 +1,447 @@
+use crate::audit::{self, AuthEventKind, AuthOutcome};
+use crate::metrics::time_password_hash;
+use crate::models::{
+    password_hash_checker, NewUser, PasswordReset, User, UserLogin, UserRegistration,
+};
+use crate::remember;
+use crate::repository::{UserRepository, USERS_EMAIL_KEY};
+use crate::services::ServiceError;
+use crate::telemetry::record_user_id;
+use crate::tokens::random_token;
+use crate::validation::{error, Validation, ALL};
+use actix_identity::Identity;
+use actix_web::{web, HttpMessage, HttpRequest};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Argon2,
+};
+use diesel::result::{DatabaseErrorKind, Error};
+use lazy_static::lazy_static;
+use std::borrow::Cow;
+use std::sync::Arc;
+use validator::{ValidationError, ValidationErrors};
+
+fn email_taken(email: &str) -> ValidationError {
+    let mut taken = error("email_taken", "Email is already registered");
+    taken.add_param(Cow::Borrowed("value"), &email);
+    taken
+}
+
+lazy_static! {
+    ///Hash the password of unknown emails is checked against, so they take as
+    ///long to turn down as wrong passwords.
+    static ref DUMMY_HASH: String = password_hasher(&random_token(16)).unwrap();
+}
+
+pub(crate) fn password_hasher(password_str: &str) -> Result<String, argon2::password_hash::Error> {
+    let password = password_str.as_bytes();
+    let salt = SaltString::generate(&mut OsRng);
+    let argon2 = Argon2::default();
+    let password_hash = argon2.hash_password(password, &salt)?.to_string();
+    Ok(password_hash)
+}
+
+///Credential checks and account creation. The plain methods only touch the
+///[`UserRepository`]; [`AuthService::login`], [`AuthService::register`] and
+///[`AuthService::logout`] additionally manage the session and the audit log.
+pub struct AuthService {
+    users: Arc<dyn UserRepository>,
+}
+
+impl AuthService {
+    pub fn new(users: Arc<dyn UserRepository>) -> AuthService {
+        AuthService { users }
+    }
+
+    ///Returns the user the credentials belong to. Unknown emails and wrong
+    ///passwords get the same error after the same Argon2 work, so neither the
+    ///response nor its timing tells them apart.
+    pub async fn authenticate(&self, login: &UserLogin) -> Result<User, ValidationErrors> {
+        let mut validation = Validation::new(login);
+        let mut user = None;
+        if let (Some(email), Some(password)) = (login.email.clone(), login.password.clone()) {
+            let users = self.users.clone();
+            validation
+                .check(ALL, async {
+                    //lookup and Argon2 verification both block, keep them off the executor
+                    let found = web::block(move || {
+                        let user = users.find_by_email(&email).ok();
+                        let password_hash = user
+                            .as_ref()
+                            .map_or(DUMMY_HASH.as_str(), |user| user.password.as_str());
+                        time_password_hash("verify", || {
+                            password_hash_checker(&password, password_hash)
+                        })
+                        .ok()?;
+                        user
+                    })
+                    .await
+                    .ok()
+                    .flatten();
+                    user = found;
+                    match user {
+                        Some(_) => Ok(()),
+                        None => Err(error("invalid", "Invalid Credentials")),
+                    }
+                })
+                .await;
+        };
+        validation.finish()?;
+        Ok(user.unwrap())
+    }
+
+    pub async fn create_account(
+        &self,
+        registration: UserRegistration,
+    ) -> Result<User, ServiceError> {
+        let mut validation = Validation::new(&registration);
+        if let Some(email) = registration.email.clone() {
+            let users = self.users.clone();
+            validation
+                .check("email", async move {
+                    let value = email.clone();
+                    let taken = web::block(move || users.find_by_email(&value).is_ok())
+                        .await
+                        .unwrap_or(false);
+                    if taken {
+                        return Err(email_taken(&email));
+                    };
+                    Ok(())
+                })
+                .await;
+        };
+        validation.finish()?;
+        let UserRegistration {
+            first_name,
+            last_name,
+            email,
+            _password,
+            ..
+        } = registration;
+        let users = self.users.clone();
+        let registered_email = email.clone().unwrap();
+        let created = web::block(move || {
+            let password = _password.unwrap();
+            let hashed_password = time_password_hash("hash", || password_hasher(&password));
+            let hashed_password = hashed_password.map_err(|e| {
+                log::error!("Error hashing password: {e}");
+                ServiceError::Internal
+            })?;
+            let new_user = NewUser {
+                first_name: first_name.unwrap(),
+                last_name: last_name.unwrap(),
+                email: email.unwrap(),
+                password: hashed_password,
+            };
+            users.create(new_user).map_err(|e| match e {
+                //registered by a concurrent request since the uniqueness check
+                Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info)
+                    if info.constraint_name() == Some(USERS_EMAIL_KEY) =>
+                {
+                    let mut errors = ValidationErrors::new();
+                    errors.add("email", email_taken(&registered_email));
+                    ServiceError::Invalid(errors)
+                }
+                e => ServiceError::from(e),
+            })
+        })
+        .await;
+        created.unwrap_or_else(|e| {
+            log::error!("Error creating user: {e}");
+            Err(ServiceError::Internal)
+        })
+    }
+
+    ///Replaces the user's password and revokes their remember-me tokens, so
+    ///devices that only stayed signed in through one have to sign in again.
+    pub async fn reset_password(
+        &self,
+        user_id: i32,
+        reset: PasswordReset,
+    ) -> Result<User, ServiceError> {
+        Validation::new(&reset).finish()?;
+        let users = self.users.clone();
+        let updated = web::block(move || {
+            let password = reset._password.unwrap();
+            let hashed_password = time_password_hash("hash", || password_hasher(&password));
+            let hashed_password = hashed_password.map_err(|e| {
+                log::error!("Error hashing password: {e}");
+                ServiceError::Internal
+            })?;
+            let user = users.set_password(user_id, &hashed_password)?;
+            users.revoke_remember_tokens(user.id)?;
+            Ok(user)
+        })
+        .await;
+        updated.unwrap_or_else(|e| {
+            log::error!("Error resetting password: {e}");
+            Err(ServiceError::Internal)
+        })
+    }
+
+    ///Checks the credentials and starts a session for the user. Both outcomes
+    ///are recorded in the audit log.
+    pub async fn login(
+        &self,
+        req: &HttpRequest,
+        login: &UserLogin,
+    ) -> Result<User, ValidationErrors> {
+        let email = login.email.as_deref();
+        let user = match self.authenticate(login).await {
+            Ok(user) => user,
+            Err(e) => {
+                let users = self.users.clone();
+                let value = email.map(String::from);
+                let user_id = web::block(move || Some(users.find_by_email(&value?).ok()?.id))
+                    .await
+                    .ok()
+                    .flatten();
+                audit::record(
+                    req,
+                    AuthEventKind::Login,
+                    AuthOutcome::Failure,
+                    user_id,
+                    email,
+                );
+                return Err(e);
+            }
+        };
+        Identity::login(&req.extensions(), user.id.to_string()).unwrap();
+        record_user_id(user.id);
+        audit::record(
+            req,
+            AuthEventKind::Login,
+            AuthOutcome::Success,
+            Some(user.id),
+            email,
+        );
+        Ok(user)
+    }
+
+    ///Creates the user and starts a session for them. Both outcomes are
+    ///recorded in the audit log.
+    pub async fn register(
+        &self,
+        req: &HttpRequest,
+        registration: UserRegistration,
+    ) -> Result<User, ServiceError> {
+        let email = registration.email.clone();
+        let user = match self.create_account(registration).await {
+            Ok(user) => user,
+            Err(e) => {
+                audit::record(
+                    req,
+                    AuthEventKind::Register,
+                    AuthOutcome::Failure,
+                    None,
+                    email.as_deref(),
+                );
+                return Err(e);
+            }
+        };
+        Identity::login(&req.extensions(), user.id.to_string()).unwrap();
+        record_user_id(user.id);
+        audit::record(
+            req,
+            AuthEventKind::Register,
+            AuthOutcome::Success,
+            Some(user.id),
+            email.as_deref(),
+        );
+        Ok(user)
+    }
+
+    ///Ends the session and revokes the request's remember-me token. Callers
+    ///still need to send [`remember::removal_cookie`] to the browser.
+    pub async fn logout(&self, req: &HttpRequest, identity: Option<Identity>) {
+        if let Some(identity) = identity {
+            let user_id = identity.id().ok().and_then(|id| id.parse::<i32>().ok());
+            audit::record(
+                req,
+                AuthEventKind::Logout,
+                AuthOutcome::Success,
+                user_id,
+                None,
+            );
+            identity.logout();
+        };
+        remember::revoke(req).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{InMemoryUserRepository, UniqueViolation};
+    use diesel::QueryResult;
+
+    fn registration(email: &str) -> UserRegistration {
+        UserRegistration {
+            first_name: Some(String::from("Peregrin")),
+            last_name: Some(String::from("Took")),
+            email: Some(String::from(email)),
+            _password: Some(String::from("Password1!")),
+            _confirm_password: Some(String::from("Password1!")),
+        }
+    }
+
+    fn login(email: &str, password: &str) -> UserLogin {
+        UserLogin {
+            email: Some(String::from(email)),
+            password: Some(String::from(password)),
+            remember: false,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_register_and_authenticate() {
+        let service = AuthService::new(Arc::new(InMemoryUserRepository::default()));
+        let user = service
+            .create_account(registration("pippin@tuckborough.com"))
+            .await
+            .unwrap();
+        assert_ne!(user.password, "Password1!");
+        let authenticated = service
+            .authenticate(&login("pippin@tuckborough.com", "Password1!"))
+            .await
+            .unwrap();
+        assert_eq!(authenticated.id, user.id);
+
+        let errors = service
+            .authenticate(&login("pippin@tuckborough.com", "Password12!"))
+            .await
+            .unwrap_err();
+        assert!(errors.errors().contains_key(ALL));
+        let errors = service
+            .authenticate(&login("merry@buckland.com", "Password1!"))
+            .await
+            .unwrap_err();
+        assert!(errors.errors().contains_key(ALL));
+    }
+
+    ///Passes the uniqueness check, then fails every insert with the given error.
+    struct FailingRepository(fn() -> Error);
+
+    impl UserRepository for FailingRepository {
+        fn find(&self, _: i32) -> QueryResult<User> {
+            Err(Error::NotFound)
+        }
+
+        fn find_by_email(&self, _: &str) -> QueryResult<User> {
+            Err(Error::NotFound)
+        }
+
+        fn list(&self) -> QueryResult<Vec<User>> {
+            Ok(vec![])
+        }
+
+        fn create(&self, _: NewUser) -> QueryResult<User> {
+            Err((self.0)())
+        }
+
+        fn set_role(&self, _: i32, _: &str) -> QueryResult<User> {
+            Err(Error::NotFound)
+        }
+
+        fn set_password(&self, _: i32, _: &str) -> QueryResult<User> {
+            Err(Error::NotFound)
+        }
+
+        fn delete(&self, _: i32) -> QueryResult<bool> {
+            Ok(false)
+        }
+
+        fn revoke_remember_tokens(&self, _: i32) -> QueryResult<usize> {
+            Ok(0)
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_classifies_insert_errors() {
+        let service = AuthService::new(Arc::new(FailingRepository(|| {
+            Error::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                Box::new(UniqueViolation(USERS_EMAIL_KEY)),
+            )
+        })));
+        match service
+            .create_account(registration("pippin@tuckborough.com"))
+            .await
+        {
+            Err(ServiceError::Invalid(errors)) => {
+                assert_eq!(errors.field_errors()["email"][0].code, "email_taken")
+            }
+            _ => panic!("expected an email field error"),
+        };
+
+        let service = AuthService::new(Arc::new(FailingRepository(|| {
+            Error::DatabaseError(
+                DatabaseErrorKind::ClosedConnection,
+                Box::new(String::from("connection refused")),
+            )
+        })));
+        let error = service
+            .create_account(registration("pippin@tuckborough.com"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), 503);
+        let errors = error.into_validation_errors();
+        assert_eq!(errors.errors().keys().next(), Some(&ALL));
+    }
+
+    fn reset(password: &str, confirmation: &str) -> PasswordReset {
+        PasswordReset {
+            _password: Some(String::from(password)),
+            _confirm_password: Some(String::from(confirmation)),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_reset_password() {
+        let service = AuthService::new(Arc::new(InMemoryUserRepository::default()));
+        let user = service
+            .create_account(registration("pippin@tuckborough.com"))
+            .await
+            .unwrap();
+        let errors = match service
+            .reset_password(user.id, reset("Password2!", "Password3!"))
+            .await
+        {
+            Err(ServiceError::Invalid(errors)) => errors,
+            _ => panic!("expected validation errors"),
+        };
+        assert_eq!(
+            errors.field_errors()["password"][0].code,
+            "password_mismatch"
+        );
+        service
+            .reset_password(user.id, reset("Password2!", "Password2!"))
+            .await
+            .unwrap();
+        assert!(service
+            .authenticate(&login("pippin@tuckborough.com", "Password1!"))
+            .await
+            .is_err());
+        assert!(service
+            .authenticate(&login("pippin@tuckborough.com", "Password2!"))
+            .await
+            .is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_duplicate_email_is_a_field_error() {
+        let service = AuthService::new(Arc::new(InMemoryUserRepository::default()));
+        service
+            .create_account(registration("pippin@tuckborough.com"))
+            .await
+            .unwrap();
+        let mut duplicate = registration("pippin@tuckborough.com");
+        duplicate.first_name = Some(String::new());
+        let errors = match service.create_account(duplicate).await {
+            Err(ServiceError::Invalid(errors)) => errors,
+            _ => panic!("expected validation errors"),
+        };
+        assert!(errors.errors().contains_key("email"));
+        assert!(errors.errors().contains_key("first_name"));
+    }
+}
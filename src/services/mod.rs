@@ -0,0 +1,70 @@
+//! Business logic shared by the HTML routes and the JSON API, so both behave
+//! the same way and only differ in how they present the outcome.
+use crate::validation::{error, ALL};
+use actix_web::http::StatusCode;
+use derive_more::Display;
+use diesel::result::{DatabaseErrorKind, Error};
+use validator::ValidationErrors;
+
+pub mod auth;
+pub mod users;
+
+///Why a service call failed. Only [`ServiceError::Invalid`] is the caller's
+///fault; the others are reported without details.
+#[derive(Debug, Display)]
+pub enum ServiceError {
+    #[display(fmt = "invalid input")]
+    Invalid(ValidationErrors),
+    ///The database can't be reached.
+    #[display(fmt = "service unavailable")]
+    Unavailable,
+    #[display(fmt = "internal error")]
+    Internal,
+}
+
+impl ServiceError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ServiceError::Invalid(_) => StatusCode::BAD_REQUEST,
+            ServiceError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ServiceError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    ///The error in the `{field: [{code, message, params}]}` shape forms
+    ///render, with failures that aren't about the input under `__all__`.
+    pub fn into_validation_errors(self) -> ValidationErrors {
+        let (code, message) = match self {
+            ServiceError::Invalid(errors) => return errors,
+            ServiceError::Unavailable => (
+                "unavailable",
+                "The service is temporarily unavailable, please try again",
+            ),
+            ServiceError::Internal => ("internal_error", "An internal error occured"),
+        };
+        let mut errors = ValidationErrors::new();
+        errors.add(ALL, error(code, message));
+        errors
+    }
+}
+
+impl From<ValidationErrors> for ServiceError {
+    fn from(errors: ValidationErrors) -> Self {
+        ServiceError::Invalid(errors)
+    }
+}
+
+impl From<Error> for ServiceError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::DatabaseError(DatabaseErrorKind::ClosedConnection, info) => {
+                log::error!("Database unavailable: {}", info.message());
+                ServiceError::Unavailable
+            }
+            e => {
+                log::error!("Database error: {e}");
+                ServiceError::Internal
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use crate::models::User;
+use crate::repository::UserRepository;
+use diesel::QueryResult;
+use std::sync::Arc;
+
+pub const ROLES: [&str; 2] = ["user", "admin"];
+
+pub struct UserService {
+    users: Arc<dyn UserRepository>,
+}
+
+impl UserService {
+    pub fn new(users: Arc<dyn UserRepository>) -> UserService {
+        UserService { users }
+    }
+
+    pub fn list(&self) -> QueryResult<Vec<User>> {
+        self.users.list()
+    }
+
+    pub fn find(&self, user_id: i32) -> QueryResult<User> {
+        self.users.find(user_id)
+    }
+
+    pub fn find_by_email(&self, email: &str) -> QueryResult<User> {
+        self.users.find_by_email(email)
+    }
+
+    ///Changes the user's role. Callers must check the role is one of [`ROLES`].
+    pub fn set_role(&self, user_id: i32, role: &str) -> QueryResult<User> {
+        self.users.set_role(user_id, role)
+    }
+
+    ///Deletes the user, returning whether it existed.
+    pub fn delete_user(&self, user_id: i32) -> QueryResult<bool> {
+        self.users.delete(user_id)
+    }
+}
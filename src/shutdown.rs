@@ -0,0 +1,151 @@
+//! Graceful shutdown. On SIGTERM or SIGINT the app first reports itself unready
+//! on `/readyz`, waits for load balancers to notice, then stops accepting
+//! connections and gives the requests in flight the drain timeout to finish.
+//! Background workers watch the same [`Shutdown`] and stop between runs.
+use actix_web::dev::Server;
+use actix_web::rt::{self, time::sleep};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+///Shared shutdown state. Cloning it gives another handle on the same state.
+#[derive(Clone)]
+pub struct Shutdown {
+    started: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        let (started, _) = watch::channel(false);
+        Shutdown {
+            started: Arc::new(started),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn begin(&self) {
+        self.started.send_replace(true);
+    }
+
+    pub fn is_started(&self) -> bool {
+        *self.started.borrow()
+    }
+
+    ///Resolves once shutdown has begun.
+    pub async fn wait(&self) {
+        let mut started = self.started.subscribe();
+        while !*started.borrow_and_update() {
+            if started.changed().await.is_err() {
+                return;
+            };
+        }
+    }
+}
+
+///Waits for SIGTERM or SIGINT, returning the name of the signal.
+#[cfg(unix)]
+pub async fn signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate()).expect("Error handling SIGTERM");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("Error handling SIGINT");
+    tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn signal() -> &'static str {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Error handling Ctrl-C");
+    "SIGINT"
+}
+
+///Runs the server until `stop` resolves, then shuts down: readiness flips
+///first, `delay` later the server stops accepting connections and drains.
+///The server must be built with `disable_signals()`, and its
+///`shutdown_timeout` bounds the drain.
+pub async fn serve(
+    server: Server,
+    shutdown: Shutdown,
+    stop: impl Future<Output = ()> + 'static,
+    delay: Duration,
+) -> io::Result<()> {
+    let handle = server.handle();
+    rt::spawn(async move {
+        stop.await;
+        shutdown.begin();
+        if !delay.is_zero() {
+            tracing::info!(delay_secs = delay.as_secs_f64(), "reporting unready");
+            sleep(delay).await;
+        };
+        tracing::info!("draining connections");
+        handle.stop(true).await;
+    });
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpServer};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use tokio::sync::oneshot;
+
+    #[actix_web::test]
+    async fn test_in_flight_requests_complete() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/slow",
+                web::get().to(|| async {
+                    sleep(Duration::from_millis(500)).await;
+                    "done"
+                }),
+            )
+        })
+        .workers(1)
+        .disable_signals()
+        .shutdown_timeout(5)
+        .listen(listener)
+        .unwrap()
+        .run();
+        let shutdown = Shutdown::default();
+        let (trigger, stop) = oneshot::channel::<()>();
+        let serving = rt::spawn(serve(
+            server,
+            shutdown.clone(),
+            async {
+                stop.await.ok();
+            },
+            Duration::ZERO,
+        ));
+        let request = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream
+                .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        sleep(Duration::from_millis(100)).await;
+        assert!(!shutdown.is_started());
+        trigger.send(()).unwrap();
+
+        let response = web::block(|| request.join().unwrap()).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("done"), "{response}");
+        serving.await.unwrap().unwrap();
+        assert!(shutdown.is_started());
+        shutdown.wait().await;
+        assert!(TcpStream::connect(address).is_err());
+    }
+}
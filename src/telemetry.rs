@@ -0,0 +1,174 @@
+//! Structured logging with `tracing`. Every request runs in a `request` span
+//! carrying its request id, and the user id once the user is known, so all the
+//! log lines of one request can be found together. `log` macros used across
+//! the app are forwarded into the same spans.
+use actix_identity::IdentityExt;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error, HttpMessage, HttpRequest,
+};
+use actix_web_lab::middleware::Next;
+use std::env;
+use std::time::Instant;
+use tracing::{field, Instrument, Span};
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+///Id of a request, from its `X-Request-Id` header or generated.
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+///Installs the global subscriber. RUST_LOG sets the filter (default `info`),
+///and LOG_FORMAT=json switches to one JSON object per line.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+        _ => subscriber.init(),
+    };
+}
+
+///Ids from upstream proxies are kept if they're short and plain enough to
+///log safely.
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+///Id of the request being handled, for code without access to the request.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+pub fn request_id(req: &HttpRequest) -> Option<String> {
+    req.extensions().get::<RequestId>().map(|id| id.0.clone())
+}
+
+///Adds the user to the current request span.
+pub fn record_user_id(user_id: i32) {
+    Span::current().record("user_id", user_id);
+}
+
+///Runs the request in a span, echoes its id in the `X-Request-Id` response
+///header and logs its outcome. Replaces actix's `Logger`.
+pub async fn trace_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| valid_request_id(id))
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+        user_id = field::Empty,
+    );
+    let started = Instant::now();
+    let response = REQUEST_ID
+        .scope(request_id.clone(), next.call(req).instrument(span.clone()))
+        .await;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let _entered = span.enter();
+    let mut response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!(latency_ms, error = %e, "request failed");
+            return Err(e);
+        }
+    };
+    let status = response.status().as_u16();
+    let route = response.request().match_pattern().unwrap_or_default();
+    if response.status().is_server_error() {
+        tracing::error!(status, route, latency_ms, "request completed");
+    } else {
+        tracing::info!(status, route, latency_ms, "request completed");
+    };
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    };
+    Ok(response)
+}
+
+///Adds the session's user to the request span. Must be wrapped inside
+///`IdentityMiddleware`, and inside `remember::restore_session` to see restored
+///sessions too.
+pub async fn record_user(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let user_id = req
+        .get_identity()
+        .ok()
+        .and_then(|identity| identity.id().ok()?.parse::<i32>().ok());
+    if let Some(user_id) = user_id {
+        record_user_id(user_id);
+    };
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ApiError;
+    use actix_web::{test, web, App, HttpResponse};
+    use actix_web_lab::middleware::from_fn;
+    use serde_json::Value;
+
+    #[actix_web::test]
+    async fn test_request_ids_are_propagated() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(trace_requests))
+                .route("/ok", web::get().to(HttpResponse::Ok))
+                .route(
+                    "/fail",
+                    web::get().to(|| async { Err::<HttpResponse, _>(ApiError::internal()) }),
+                ),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri("/ok")
+            .insert_header((REQUEST_ID_HEADER, "lb-1234"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "lb-1234"
+        );
+
+        let request = test::TestRequest::get()
+            .uri("/fail")
+            .insert_header((REQUEST_ID_HEADER, "not a safe id"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let request_id = response.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        assert!(Uuid::parse_str(request_id.to_str().unwrap()).is_ok());
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"]["request_id"], request_id.to_str().unwrap());
+    }
+}
@@ -0,0 +1,221 @@
+//! Test harness. Every test run gets a database of its own, created next to
+//! the one in DATABASE_URL (whose user needs the CREATEDB privilege) and
+//! migrated from scratch, so tests neither depend on what a developer's
+//! database holds nor leave rows behind in it. The global pool points at it
+//! too, see [`crate::database_url`].
+//!
+//! Test database names carry the time they were created at. Databases of runs
+//! that started more than [`STALE_AFTER`] ago are dropped when the next run
+//! starts, so concurrent runs never drop each other's database.
+use crate::app::AppConfig;
+use crate::mailer::MemoryMailer;
+use crate::migrations::MIGRATIONS;
+use crate::models::User;
+use crate::proxy::TrustedProxies;
+use crate::repository::DieselUserRepository;
+use crate::shutdown::Shutdown;
+use crate::{fixtures, DbPool};
+use actix_http::Request;
+use actix_web::test::{call_service, TestRequest};
+use actix_web::{
+    body::MessageBody,
+    cookie::{Cookie, Key},
+    dev::{Service, ServiceResponse},
+    Error,
+};
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+    sql_query,
+    sql_types::Text,
+};
+use diesel_migrations::MigrationHarness;
+use dotenvy::dotenv;
+use lazy_static::lazy_static;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+///Prefix of the names of test databases.
+const TEST_DATABASE_PREFIX: &str = "web_app_test_";
+
+///Age after which the database of an earlier run is dropped.
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+///Password of the users made by [`create_user`].
+pub const TEST_PASSWORD: &str = "Password1!";
+
+lazy_static! {
+    static ref TEST_DATABASE_URL: String = create_database();
+}
+
+#[derive(QueryableByName)]
+struct Database {
+    #[diesel(sql_type = Text)]
+    datname: String,
+}
+
+///The URL with its database name replaced.
+fn with_database(url: &str, name: &str) -> String {
+    let (url, query) = match url.split_once('?') {
+        Some((url, query)) => (url, format!("?{query}")),
+        None => (url, String::new()),
+    };
+    let (server, _) = url
+        .rsplit_once('/')
+        .expect("DATABASE_URL must name a database");
+    format!("{server}/{name}{query}")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+///Whether the test database is from a run that started more than
+///[`STALE_AFTER`] ago, judging by the creation time in its name.
+fn is_stale(name: &str) -> bool {
+    name.strip_prefix(TEST_DATABASE_PREFIX)
+        .and_then(|rest| rest.split_once('_'))
+        .and_then(|(created, _)| created.parse::<u64>().ok())
+        .is_some_and(|created| now().saturating_sub(created) > STALE_AFTER.as_secs())
+}
+
+fn create_database() -> String {
+    dotenv().ok();
+    let url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let conn = &mut PgConnection::establish(&with_database(&url, "postgres"))
+        .unwrap_or_else(|e| panic!("Error connecting to the test database server: {e}"));
+    let leftovers = sql_query("select datname from pg_database where starts_with(datname, $1)")
+        .bind::<Text, _>(TEST_DATABASE_PREFIX)
+        .load::<Database>(conn)
+        .unwrap();
+    for database in leftovers
+        .iter()
+        .filter(|database| is_stale(&database.datname))
+    {
+        //fails for databases something is still connected to, which is fine
+        sql_query(format!(r#"drop database "{}""#, database.datname))
+            .execute(conn)
+            .ok();
+    }
+    let name = format!(
+        "{TEST_DATABASE_PREFIX}{}_{}",
+        now(),
+        Uuid::new_v4().simple()
+    );
+    sql_query(format!(r#"create database "{name}""#))
+        .execute(conn)
+        .unwrap_or_else(|e| panic!("Error creating the test database: {e}"));
+    let test_url = with_database(&url, &name);
+    PgConnection::establish(&test_url)
+        .unwrap()
+        .run_pending_migrations(MIGRATIONS)
+        .unwrap_or_else(|e| panic!("Error migrating the test database: {e}"));
+    test_url
+}
+
+pub fn database_url() -> String {
+    TEST_DATABASE_URL.clone()
+}
+
+///Pool of the test database, to hand to the app under test.
+pub fn pool() -> DbPool {
+    crate::pool().clone()
+}
+
+///Pool of a database nothing listens for, failing fast.
+pub fn unreachable_pool() -> DbPool {
+    Pool::builder()
+        .connection_timeout(Duration::from_millis(100))
+        .build_unchecked(ConnectionManager::new(
+            "postgres://postgres@127.0.0.1:1/unreachable",
+        ))
+}
+
+///Config of an app on the test database, sending emails to memory.
+pub fn config() -> AppConfig {
+    AppConfig {
+        pool: pool(),
+        mailer: Arc::new(MemoryMailer::default()),
+        oidc: None,
+        //fine for tests only, real keys come from SESSION_KEY
+        session_key: Key::from(&[0; 64]),
+        shutdown: Shutdown::default(),
+        secure_cookies: false,
+        trusted_proxies: TrustedProxies::default(),
+    }
+}
+
+///Creates a user with a unique email and [`TEST_PASSWORD`].
+pub fn create_user(role: &str) -> User {
+    let fixture = fixtures::UserFixture {
+        first_name: String::from("Frodo"),
+        last_name: String::from("Baggins"),
+        email: format!("{}@theshire.com", Uuid::new_v4()),
+        password: String::from(TEST_PASSWORD),
+        role: String::from(role),
+    };
+    fixtures::insert_user(&DieselUserRepository::new(pool()), &fixture).unwrap()
+}
+
+///Signs the user in through the app's `/login` form and returns the session
+///cookie to send with the next requests.
+pub async fn login_as<S, B>(app: &S, user: &User) -> Cookie<'static>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let request = TestRequest::post()
+        .uri("/login")
+        .set_form([("email", user.email.as_str()), ("password", TEST_PASSWORD)])
+        .to_request();
+    let response = call_service(app, request).await;
+    assert_eq!(
+        response.status(),
+        303,
+        "logging in as {} failed",
+        user.email
+    );
+    let cookie = response.response().cookies().next().unwrap();
+    cookie.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::UserRepository;
+
+    #[test]
+    fn test_replaces_the_database_name() {
+        assert_eq!(
+            with_database("postgres://postgres@127.0.0.1/web_app", "postgres"),
+            "postgres://postgres@127.0.0.1/postgres"
+        );
+        assert_eq!(
+            with_database("postgres://u:p@db:5432/web_app?sslmode=require", "other"),
+            "postgres://u:p@db:5432/other?sslmode=require"
+        );
+    }
+
+    #[test]
+    fn test_runs_against_its_own_database() {
+        assert!(database_url().contains(TEST_DATABASE_PREFIX));
+        let user = create_user("admin");
+        let found = DieselUserRepository::new(pool()).find(user.id).unwrap();
+        assert_eq!(found.role, "admin");
+    }
+
+    #[test]
+    fn test_only_old_databases_are_stale() {
+        let own = database_url().rsplit_once('/').unwrap().1.to_string();
+        assert!(!is_stale(&own));
+        let old = now() - STALE_AFTER.as_secs() - 60;
+        assert!(is_stale(&format!("{TEST_DATABASE_PREFIX}{old}_abc")));
+        assert!(!is_stale(&format!("{TEST_DATABASE_PREFIX}abc")));
+        assert!(!is_stale("web_app"));
+    }
+}
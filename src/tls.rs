@@ -0,0 +1,231 @@
+//! TLS termination with rustls. The certificate is served by a [`CertResolver`]
+//! rather than baked into the server config, so SIGHUP can swap in a renewed
+//! certificate without dropping connections. Plain HTTP listeners only
+//! redirect to HTTPS.
+use crate::proxy::ClientInfo;
+use crate::shutdown::Shutdown;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    Error, HttpRequest, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+///Reads a PEM certificate chain and its private key (PKCS#8, PKCS#1 or SEC1).
+pub fn load_certified_key(cert_path: &Path, key_path: &Path) -> io::Result<CertifiedKey> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?;
+    if certs.is_empty() {
+        return Err(invalid(format!(
+            "no certificate found in {}",
+            cert_path.display()
+        )));
+    };
+    let mut reader = BufReader::new(File::open(key_path)?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break PrivateKey(key),
+            Some(_) => continue,
+            None => {
+                return Err(invalid(format!(
+                    "no private key found in {}",
+                    key_path.display()
+                )))
+            }
+        };
+    };
+    let key = any_supported_type(&key).map_err(|e| invalid(format!("{e}")))?;
+    Ok(CertifiedKey::new(
+        certs.into_iter().map(Certificate).collect(),
+        key,
+    ))
+}
+
+///Serves the certificate last loaded from its files.
+pub struct CertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    pub fn new(cert_path: PathBuf, key_path: PathBuf) -> io::Result<CertResolver> {
+        let current = load_certified_key(&cert_path, &key_path)?;
+        Ok(CertResolver {
+            cert_path,
+            key_path,
+            current: RwLock::new(Arc::new(current)),
+        })
+    }
+
+    ///Loads the files again. On error the current certificate stays in use.
+    pub fn reload(&self) -> io::Result<()> {
+        let reloaded = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = Arc::new(reloaded);
+        Ok(())
+    }
+
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+pub fn server_config(resolver: Arc<CertResolver>) -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver)
+}
+
+///Reloads the certificate on every SIGHUP until shutdown begins.
+#[cfg(unix)]
+pub async fn reload_on_sighup(resolver: Arc<CertResolver>, shutdown: Shutdown) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = signal(SignalKind::hangup()).expect("Error handling SIGHUP");
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => (),
+            _ = shutdown.wait() => break,
+        };
+        match resolver.reload() {
+            Ok(()) => tracing::info!("reloaded TLS certificate"),
+            Err(e) => tracing::error!(error = %e, "keeping the current TLS certificate"),
+        };
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(_resolver: Arc<CertResolver>, shutdown: Shutdown) {
+    shutdown.wait().await;
+}
+
+///Where a plain HTTP request to `host` is redirected, `host` being the value
+///of its `Host` header.
+fn redirect_location(host: &str, https_port: u16, path_and_query: &str) -> String {
+    //IPv6 literals are bracketed, e.g. `[::1]:8080`
+    let hostname = match host.strip_prefix('[') {
+        Some(rest) => rest
+            .split_once(']')
+            .map_or(host, |(address, _)| &host[..address.len() + 2]),
+        None => host.split(':').next().unwrap_or(host),
+    };
+    if https_port == 443 {
+        format!("https://{hostname}{path_and_query}")
+    } else {
+        format!("https://{hostname}:{https_port}{path_and_query}")
+    }
+}
+
+///Permanently redirects to the same URL over HTTPS. 308 keeps the method and
+///body, so form posts aren't turned into GETs.
+pub fn redirect_to_https(req: &HttpRequest, https_port: u16) -> HttpResponse {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_else(|| req.app_config().host());
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+    HttpResponse::PermanentRedirect()
+        .append_header((
+            header::LOCATION,
+            redirect_location(host, https_port, path_and_query),
+        ))
+        .finish()
+}
+
+///Middleware marking the cookies of responses sent over TLS `Secure`, so the
+///browser never sends them over plain HTTP. Covers TLS terminated by a trusted
+///proxy too.
+pub async fn secure_cookies(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let secure = ClientInfo::of(req.parts_mut().0).is_https();
+    let mut res = next.call(req).await?;
+    if secure {
+        let cookies: Vec<_> = res
+            .response()
+            .cookies()
+            .filter(|cookie| cookie.secure() != Some(true))
+            .map(|cookie| cookie.into_owned())
+            .collect();
+        for mut cookie in cookies {
+            res.response_mut().del_cookie(cookie.name());
+            cookie.set_secure(true);
+            res.response_mut().add_cookie(&cookie)?;
+        }
+    };
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use uuid::Uuid;
+
+    fn write_certificate(dir: &Path) -> Vec<u8> {
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let pem = cert.serialize_pem().unwrap();
+        fs::write(dir.join("cert.pem"), &pem).unwrap();
+        fs::write(dir.join("key.pem"), cert.serialize_private_key_pem()).unwrap();
+        rustls_pemfile::certs(&mut pem.as_bytes())
+            .unwrap()
+            .remove(0)
+    }
+
+    #[test]
+    fn test_reload_swaps_the_certificate() {
+        let dir = env::temp_dir().join(format!("web_app_tls_{}", Uuid::new_v4().simple()));
+        fs::create_dir(&dir).unwrap();
+        let first = write_certificate(&dir);
+        let resolver = CertResolver::new(dir.join("cert.pem"), dir.join("key.pem")).unwrap();
+        assert_eq!(resolver.current().cert[0].0, first);
+
+        let second = write_certificate(&dir);
+        resolver.reload().unwrap();
+        assert_eq!(resolver.current().cert[0].0, second);
+
+        fs::write(dir.join("cert.pem"), "not a certificate").unwrap();
+        assert!(resolver.reload().is_err());
+        assert_eq!(resolver.current().cert[0].0, second);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_redirect_location() {
+        assert_eq!(
+            redirect_location("example.com", 443, "/login?next=%2F"),
+            "https://example.com/login?next=%2F"
+        );
+        assert_eq!(
+            redirect_location("example.com:8080", 8443, "/"),
+            "https://example.com:8443/"
+        );
+        assert_eq!(
+            redirect_location("[::1]:8080", 8443, "/home"),
+            "https://[::1]:8443/home"
+        );
+        assert_eq!(redirect_location("[::1]", 443, "/"), "https://[::1]/");
+    }
+}
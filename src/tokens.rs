@@ -0,0 +1,57 @@
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+///Generates `len` random bytes from the OS RNG, hex encoded.
+pub fn random_token(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+///Tokens are high entropy secrets, so a plain SHA-256 is enough to keep them
+///out of the database (unlike passwords, which go through Argon2).
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+///Whether `token` hashes to `stored_hash`. Compares in constant time, so the
+///response time doesn't tell how much of the hash matched.
+pub fn hash_matches(stored_hash: &str, token: &str) -> bool {
+    stored_hash
+        .as_bytes()
+        .ct_eq(hash_token(token).as_bytes())
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_token_length_and_uniqueness() {
+        let token = random_token(16);
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, random_token(16));
+    }
+
+    #[test]
+    fn test_hash_token() {
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_hash_matches() {
+        let stored = hash_token("abc");
+        assert!(hash_matches(&stored, "abc"));
+        assert!(!hash_matches(&stored, "abd"));
+        assert!(!hash_matches(&stored[..10], "abc"));
+    }
+}
@@ -0,0 +1,91 @@
+//! Two phase validation. [`Validation::new`] runs the syntactic checks declared
+//! with `validator` attributes, which never touch the database. Business rules
+//! such as email uniqueness or credential checks are then run with
+//! [`Validation::check`], which is async so rules can query storage without
+//! blocking. Both phases report into one `ValidationErrors`, so responses keep
+//! the `{field: [{code, message, params}]}` shape.
+use std::borrow::Cow;
+use std::future::Future;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+///Key `validator` uses for errors that aren't about a single field.
+pub const ALL: &str = "__all__";
+
+pub struct Validation {
+    errors: ValidationErrors,
+}
+
+impl Validation {
+    pub fn new<T: Validate>(input: &T) -> Validation {
+        Validation {
+            errors: input.validate().err().unwrap_or_default(),
+        }
+    }
+
+    pub fn is_valid(&self, field: &'static str) -> bool {
+        !self.errors.errors().contains_key(field)
+    }
+
+    ///Runs a business rule for the field, unless the field already failed, so
+    ///rules can rely on its value being well formed.
+    pub async fn check<F>(&mut self, field: &'static str, rule: F)
+    where
+        F: Future<Output = Result<(), ValidationError>>,
+    {
+        if !self.is_valid(field) {
+            return;
+        };
+        if let Err(e) = rule.await {
+            self.errors.add(field, e);
+        };
+    }
+
+    pub fn finish(self) -> Result<(), ValidationErrors> {
+        if self.errors.errors().is_empty() {
+            return Ok(());
+        };
+        Err(self.errors)
+    }
+}
+
+pub fn error(code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Borrowed(message));
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Validate)]
+    struct Input {
+        #[validate(length(min = 1))]
+        name: String,
+    }
+
+    #[actix_web::test]
+    async fn test_rules_skip_invalid_fields() {
+        let mut validation = Validation::new(&Input {
+            name: String::new(),
+        });
+        validation
+            .check("name", async { panic!("rule ran on an invalid field") })
+            .await;
+        validation
+            .check(ALL, async { Err(error("invalid", "Invalid")) })
+            .await;
+        let errors = validation.finish().unwrap_err();
+        assert_eq!(errors.field_errors()["name"][0].code, "length");
+        assert_eq!(errors.errors().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_valid_input() {
+        let mut validation = Validation::new(&Input {
+            name: String::from("Frodo"),
+        });
+        validation.check("name", async { Ok(()) }).await;
+        assert!(validation.finish().is_ok());
+    }
+}
@@ -0,0 +1,75 @@
+//! Background work running next to the server. Emails are sent by the request
+//! that triggers them, so the only worker is the session sweeper. Workers stop
+//! between runs once [`Shutdown`] begins; `main` waits for them before exiting.
+use crate::shutdown::Shutdown;
+use crate::{magic_link, remember, DbPool};
+use actix_web::rt::{self, task::JoinHandle, time::interval};
+use actix_web::web;
+use diesel::QueryResult;
+use std::env;
+use std::time::Duration;
+
+///Seconds between session sweeps, from SESSION_SWEEP_INTERVAL. Defaults to
+///an hour.
+fn sweep_interval() -> Duration {
+    let seconds = env::var("SESSION_SWEEP_INTERVAL")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(3600);
+    Duration::from_secs(seconds)
+}
+
+pub fn spawn(shutdown: &Shutdown, pool: &DbPool) -> Vec<JoinHandle<()>> {
+    vec![rt::spawn(sweep_sessions(
+        shutdown.clone(),
+        pool.clone(),
+        sweep_interval(),
+    ))]
+}
+
+fn sweep(pool: &DbPool) -> QueryResult<(usize, usize)> {
+    Ok((remember::purge_expired(pool)?, magic_link::purge(pool)?))
+}
+
+///Deletes expired remember-me tokens and spent login links, like
+///`web_app-admin sessions purge` does.
+async fn sweep_sessions(shutdown: Shutdown, pool: DbPool, every: Duration) {
+    let mut ticks = interval(every);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => (),
+            _ = shutdown.wait() => break,
+        };
+        let pool = pool.clone();
+        match web::block(move || sweep(&pool)).await {
+            Ok(Ok((tokens, links))) => tracing::debug!(tokens, links, "swept sessions"),
+            Ok(Err(e)) => tracing::error!(error = %e, "session sweep failed"),
+            Err(e) => tracing::error!(error = %e, "session sweep failed"),
+        };
+    }
+    tracing::info!("session sweeper stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::rt::time::timeout;
+
+    #[actix_web::test]
+    async fn test_sweeper_stops_on_shutdown() {
+        let shutdown = Shutdown::default();
+        let sweeper = rt::spawn(sweep_sessions(
+            shutdown.clone(),
+            testing::pool(),
+            Duration::from_millis(10),
+        ));
+        rt::time::sleep(Duration::from_millis(50)).await;
+        assert!(!sweeper.is_finished());
+        shutdown.begin();
+        timeout(Duration::from_secs(5), sweeper)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
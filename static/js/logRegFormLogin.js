@@ -20,7 +20,10 @@ document.getElementById("logRegForm").addEventListener("submit", async (e) => {
   async function postForm(body) {
     const req = await fetch("/login", {
       method: "POST",
-      headers: { "Content-Type": "application/json" },
+      headers: {
+        Accept: "application/json",
+        "Content-Type": "application/json",
+      },
       body,
     });
     const res = await req.json();
@@ -19,6 +19,9 @@ document.getElementById("logRegForm").addEventListener("submit", async (e) => {
   errors.forEach((field) => {
     document.getElementById(`validation_${field}`).innerText = "";
   });
+  const formError = document.getElementById("validation___all__");
+  formError.innerText = "";
+  formError.classList.add("d-none");
 
   let formData = new FormData(e.target);
   let body = JSON.stringify(Object.fromEntries(formData));
@@ -26,7 +29,10 @@ document.getElementById("logRegForm").addEventListener("submit", async (e) => {
   async function postForm(body) {
     const req = await fetch("/register", {
       method: "POST",
-      headers: { "Content-Type": "application/json" },
+      headers: {
+        Accept: "application/json",
+        "Content-Type": "application/json",
+      },
       body,
     });
     const res = await req.json();
@@ -52,5 +58,13 @@ document.getElementById("logRegForm").addEventListener("submit", async (e) => {
         .getElementById(`${field}`)
         .addEventListener("click", feedbackListener);
     });
+    //errors that aren't about a single field, e.g. the database being down
+    if ("__all__" in response) {
+      formError.innerText = response.__all__
+        .map((err) => err.message)
+        .filter((message) => message !== null)
+        .join(" ");
+      formError.classList.remove("d-none");
+    }
   }
 });